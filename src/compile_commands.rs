@@ -0,0 +1,222 @@
+use std::io::{self, Read};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use serde_json::{self, Value};
+
+use io_utils::invalid_data;
+use path_utils::SearchPaths;
+use preprocessor::{self, MacroMap};
+
+// ----------------------------------------------------------------------------
+
+// One translation unit from a JSON compilation database, with the flags that
+// affect include resolution.
+#[derive(Debug, Clone, Default)]
+pub struct CompileCommand {
+    pub file: PathBuf, // Absolute path of the compiled file.
+    pub compiler: String, // The first argument, e.g. "c++".
+    pub quote_paths: Vec<PathBuf>, // -iquote
+    pub include_paths: Vec<PathBuf>, // -I
+    pub system_paths: Vec<PathBuf>, // -isystem
+    pub builtin_paths: Vec<PathBuf>, // The compiler's own system directories. Not in the database.
+    pub after_paths: Vec<PathBuf>, // -idirafter
+    pub defines: MacroMap, // -D, minus any -U
+}
+
+impl CompileCommand {
+    // All search paths, in the order the compiler would search them.
+    pub fn search_paths(&self) -> SearchPaths {
+        SearchPaths {
+            quote: self.quote_paths.clone(),
+            angle: self.include_paths
+                .iter()
+                .chain(&self.system_paths)
+                .chain(&self.builtin_paths)
+                .chain(&self.after_paths)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Read a compile_commands.json file.
+pub fn load_compile_commands(path: &Path) -> Result<Vec<CompileCommand>, io::Error> {
+    let mut f = File::open(path)?;
+    let mut text = String::new();
+    f.read_to_string(&mut text)?;
+
+    parse_compile_commands(&text)
+}
+
+// Parse the text of a JSON compilation database. Each entry needs a
+// "directory", a "file", and either an "arguments" list or a "command" string.
+pub fn parse_compile_commands(text: &str) -> Result<Vec<CompileCommand>, io::Error> {
+    let json: Value = serde_json::from_str(text).map_err(|err| invalid_data(&err.to_string()))?;
+
+    let entries = json.as_array()
+        .ok_or_else(|| invalid_data("Compilation database is not a JSON array"))?;
+
+    let mut commands = Vec::new();
+    for entry in entries {
+        let directory = entry.get("directory")
+            .and_then(Value::as_str)
+            .map(PathBuf::from)
+            .ok_or_else(|| invalid_data("Compile command is missing \"directory\""))?;
+        let file = entry.get("file")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid_data("Compile command is missing \"file\""))?;
+
+        let arguments = match (entry.get("arguments").and_then(Value::as_array),
+                               entry.get("command").and_then(Value::as_str)) {
+            (Some(arguments), _) => {
+                arguments.iter().filter_map(Value::as_str).map(String::from).collect()
+            }
+            (None, Some(command)) => split_command_line(command),
+            (None, None) => {
+                return Err(invalid_data("Compile command has no \"arguments\" or \"command\""))
+            }
+        };
+
+        let mut command = parse_arguments(&arguments, &directory);
+        command.file = absolute_path(&directory, file);
+        commands.push(command);
+    }
+
+    Ok(commands)
+}
+
+// Make a path from the database absolute, relative to the entry's directory.
+fn absolute_path(directory: &Path, path: &str) -> PathBuf {
+    let full_path = directory.join(path);
+    fs::canonicalize(&full_path).unwrap_or(full_path)
+}
+
+// ----------------------------------------------------------------------------
+
+// Collect the include paths and macro definitions from compiler arguments.
+// Both '-Ipath' and '-I path' forms are accepted.
+fn parse_arguments(arguments: &[String], directory: &Path) -> CompileCommand {
+    let mut command = CompileCommand {
+        compiler: arguments.first().cloned().unwrap_or_default(),
+        ..CompileCommand::default()
+    };
+
+    let mut iter = arguments.iter();
+    while let Some(arg) = iter.next() {
+        if let Some(value) = flag_value("-iquote", arg, &mut iter) {
+            command.quote_paths.push(absolute_path(directory, &value));
+        } else if let Some(value) = flag_value("-isystem", arg, &mut iter) {
+            command.system_paths.push(absolute_path(directory, &value));
+        } else if let Some(value) = flag_value("-idirafter", arg, &mut iter) {
+            command.after_paths.push(absolute_path(directory, &value));
+        } else if let Some(value) = flag_value("-I", arg, &mut iter) {
+            command.include_paths.push(absolute_path(directory, &value));
+        } else if let Some(value) = flag_value("-D", arg, &mut iter) {
+            let (name, value) = preprocessor::parse_define(&value);
+            command.defines.insert(name, value);
+        } else if let Some(value) = flag_value("-U", arg, &mut iter) {
+            command.defines.remove(value.trim());
+        }
+    }
+
+    command
+}
+
+// If [arg] is [flag], return the next argument. If [arg] starts with [flag],
+// return the rest of it.
+fn flag_value<'a, I>(flag: &str, arg: &str, iter: &mut I) -> Option<String>
+    where I: Iterator<Item = &'a String>
+{
+    if arg == flag {
+        iter.next().cloned()
+    } else {
+        arg.strip_prefix(flag).map(String::from)
+    }
+}
+
+// Split a shell command line into arguments, handling quotes and escapes.
+fn split_command_line(command: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut in_argument = false;
+    let mut quote = None;
+
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => current.push(c),
+            (Some('"'), '"') => quote = None,
+            (_, '\\') => {
+                current.extend(chars.next());
+                in_argument = true;
+            }
+            (None, '\'') | (None, '"') => {
+                quote = Some(c);
+                in_argument = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_argument {
+                    arguments.push(current.clone());
+                    current.clear();
+                    in_argument = false;
+                }
+            }
+            _ => {
+                current.push(c);
+                in_argument = true;
+            }
+        }
+    }
+
+    if in_argument {
+        arguments.push(current);
+    }
+
+    arguments
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn split_commands() {
+        assert_eq!(split_command_line(r#"c++  -DNAME="a b" -I 'x y' a\ b.cpp """#),
+                   vec!["c++", "-DNAME=a b", "-I", "x y", "a b.cpp", ""]);
+    }
+
+    #[test]
+    fn parse_flags() {
+        let text = r#"[{
+            "directory": "/build",
+            "command": "c++ -I../inc -isystem /sys -iquote q -DA -DB=2 -UA -c ../src/a.cpp",
+            "file": "../src/a.cpp"
+        }]"#;
+
+        let commands = parse_compile_commands(text).unwrap();
+        assert_eq!(commands.len(), 1);
+
+        let command = &commands[0];
+        assert_eq!(command.file, PathBuf::from("/build/../src/a.cpp"));
+        assert_eq!(command.compiler, "c++");
+        let search_paths = command.search_paths();
+        assert_eq!(search_paths.quote, vec![PathBuf::from("/build/q")]);
+        assert_eq!(search_paths.angle,
+                   vec![PathBuf::from("/build/../inc"), PathBuf::from("/sys")]);
+        assert_eq!(command.defines.len(), 1);
+        assert_eq!(command.defines["B"], "2");
+    }
+
+    #[test]
+    fn reject_invalid_database() {
+        assert!(parse_compile_commands("{}").is_err());
+        assert!(parse_compile_commands(r#"[{"directory": "/", "file": "a.c"}]"#).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+
+use std::io::{self, Read};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use find_includes::QuoteTypes;
+use io_utils::invalid_data;
+use layer_rules::{LayerRule, LayerRules};
+
+// Name of the project config file, looked for in the source directory and
+// its parents.
+pub const CONFIG_FILE_NAME: &str = "include2dot.toml";
+
+// ----------------------------------------------------------------------------
+
+// How the graph is drawn.
+#[derive(Debug, Clone, Default)]
+pub struct StyleConfig {
+    pub groups: Option<bool>, // Cluster files into directory groups.
+    pub merge: Option<String>, // file, module or directory.
+    pub format: Option<String>, // Output format, e.g. "svg".
+    pub cycle_color: Option<String>, // Color of edges on include cycles.
+    pub violation_color: Option<String>, // Color of edges that break a layering rule.
+}
+
+// Project settings, read from an include2dot.toml file. Options left out of
+// the file are None or empty, so command line flags and defaults apply. For
+// example:
+//
+//     include = ["include", "third_party"]
+//     iquote = ["src"]
+//     extensions = ["h", "cpp", "inl"]
+//     sniff = true
+//     exclude = "test_|generated"
+//     exclude_globs = ["third_party/", "build*/"]
+//     include_globs = ["src/", "include/"]
+//     respect_ignore = true
+//     hidden = false
+//     follow_links = false
+//     quotetypes = "both"
+//     defines = ["NDEBUG", "VERSION=2"]
+//     rules = ["core/** may not include ui/**"]
+//     rules_file = "layers.txt"
+//
+//     [style]
+//     groups = true
+//     merge = "module"
+//     format = "svg"
+//     cycle_color = "red"
+//     violation_color = "orange"
+//
+// Relative paths are relative to the directory holding the config file,
+// except for exclude_globs and include_globs. Those are matched against paths
+// in the scanned tree (--src), like the globs given on the command line.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub path: PathBuf, // The file the config was read from.
+    pub include: Vec<PathBuf>,
+    pub iquote: Vec<PathBuf>,
+    pub extensions: Option<Vec<String>>,
+    pub sniff: Option<bool>, // Scan extensionless files that look like C or C++.
+    pub exclude: Option<String>, // Regular expression of file names to skip.
+    pub exclude_globs: Vec<String>, // Gitignore-style globs of paths to skip.
+    pub include_globs: Vec<String>, // Gitignore-style globs of the only paths to scan.
+    pub respect_ignore: Option<bool>, // Skip files listed in .gitignore and .ignore files.
+    pub hidden: Option<bool>, // Walk hidden directories.
+    pub follow_links: Option<bool>, // Follow symbolic links.
+    pub quote_types: Option<QuoteTypes>,
+    pub defines: Vec<String>, // As NAME or NAME=VALUE.
+    pub rules: LayerRules, // Inline rules, followed by the ones from rules_file.
+    pub style: StyleConfig,
+}
+
+impl Config {
+    // Find the config file for a source directory, by looking in it and then
+    // in each of its parents.
+    pub fn find(src_dir: &Path) -> Option<PathBuf> {
+        src_dir.ancestors()
+            .map(|dir| dir.join(CONFIG_FILE_NAME))
+            .find(|path| path.is_file())
+    }
+
+    // Read a config file.
+    pub fn load(path: &Path) -> Result<Config, io::Error> {
+        let mut f = File::open(path)?;
+        let mut text = String::new();
+        f.read_to_string(&mut text)?;
+
+        let mut config = Config::parse(&text, path.parent().unwrap_or_else(|| Path::new("")))?;
+        config.path = path.to_path_buf();
+        Ok(config)
+    }
+
+    // Parse the text of a config file. Relative paths are joined to [base_dir].
+    pub fn parse(text: &str, base_dir: &Path) -> Result<Config, io::Error> {
+        let table = text.parse::<Table>().map_err(|err| invalid_data(&err.to_string()))?;
+
+        let mut config = Config::default();
+        for (key, value) in &table {
+            match key.as_str() {
+                "include" => config.include = paths(key, value, base_dir)?,
+                "iquote" => config.iquote = paths(key, value, base_dir)?,
+                "extensions" => config.extensions = Some(strings(key, value)?),
+                "sniff" => config.sniff = Some(boolean(key, value)?),
+                "exclude" => config.exclude = Some(string(key, value)?),
+                "exclude_globs" => config.exclude_globs = strings(key, value)?,
+                "include_globs" => config.include_globs = strings(key, value)?,
+                "respect_ignore" => config.respect_ignore = Some(boolean(key, value)?),
+                "hidden" => config.hidden = Some(boolean(key, value)?),
+                "follow_links" => config.follow_links = Some(boolean(key, value)?),
+                "quotetypes" => {
+                    config.quote_types = Some(string(key, value)?.parse().map_err(|err: String| invalid_data(&err))?)
+                }
+                "defines" => config.defines = strings(key, value)?,
+                "rules" => {
+                    for rule in strings(key, value)? {
+                        config.rules.rules.push(LayerRule::parse(&rule).map_err(|err| invalid_data(&err))?);
+                    }
+                }
+                "rules_file" => {
+                    let rules = LayerRules::load(&base_dir.join(string(key, value)?))?;
+                    config.rules.rules.extend(rules.rules);
+                }
+                "style" => config.style = parse_style(value)?,
+                _ => return Err(invalid_data(&format!("Unknown setting \"{}\"", key))),
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_style(value: &Value) -> Result<StyleConfig, io::Error> {
+    let table = value.as_table().ok_or_else(|| invalid_data("\"style\" should be a table"))?;
+
+    let mut style = StyleConfig::default();
+    for (key, value) in table {
+        match key.as_str() {
+            "groups" => style.groups = Some(boolean("style.groups", value)?),
+            "merge" => style.merge = Some(string(key, value)?),
+            "format" => style.format = Some(string(key, value)?),
+            "cycle_color" => style.cycle_color = Some(string(key, value)?),
+            "violation_color" => style.violation_color = Some(string(key, value)?),
+            _ => return Err(invalid_data(&format!("Unknown setting \"style.{}\"", key))),
+        }
+    }
+
+    Ok(style)
+}
+
+fn string(key: &str, value: &Value) -> Result<String, io::Error> {
+    value.as_str()
+        .map(String::from)
+        .ok_or_else(|| invalid_data(&format!("\"{}\" should be a string", key)))
+}
+
+fn boolean(key: &str, value: &Value) -> Result<bool, io::Error> {
+    value.as_bool().ok_or_else(|| invalid_data(&format!("\"{}\" should be true or false", key)))
+}
+
+fn strings(key: &str, value: &Value) -> Result<Vec<String>, io::Error> {
+    value.as_array()
+        .and_then(|values| values.iter().map(|value| value.as_str().map(String::from)).collect())
+        .ok_or_else(|| invalid_data(&format!("\"{}\" should be a list of strings", key)))
+}
+
+fn paths(key: &str, value: &Value, base_dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+    Ok(strings(key, value)?.iter().map(|path| base_dir.join(path)).collect())
+}
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_parse_config() {
+    let config = Config::parse("include = [\"include\", \"/opt/sdk\"]\n\
+                                extensions = [\"h\", \"inl\"]\n\
+                                sniff = true\n\
+                                exclude_globs = [\"third_party/\"]\n\
+                                respect_ignore = true\n\
+                                quotetypes = \"Angle\"\n\
+                                rules = [\"core/** may not include ui/**\"]\n\
+                                \n\
+                                [style]\n\
+                                groups = true\n\
+                                format = \"svg\"\n",
+                               Path::new("/project"))
+        .unwrap();
+
+    assert_eq!(config.include, vec![PathBuf::from("/project/include"), PathBuf::from("/opt/sdk")]);
+    assert_eq!(config.extensions, Some(vec![String::from("h"), String::from("inl")]));
+    assert_eq!(config.sniff, Some(true));
+    assert_eq!(config.quote_types, Some(QuoteTypes::Angle));
+    assert_eq!(config.rules.rules[0].text, "core/** may not include ui/**");
+    assert_eq!(config.style.groups, Some(true));
+    assert_eq!(config.style.format, Some(String::from("svg")));
+    assert_eq!(config.style.merge, None);
+    assert!(config.exclude.is_none());
+    assert_eq!(config.exclude_globs, vec![String::from("third_party/")]);
+    assert!(config.include_globs.is_empty());
+    assert_eq!(config.respect_ignore, Some(true));
+    assert_eq!(config.follow_links, None);
+
+    // Typos are reported, rather than silently ignored.
+    let err = Config::parse("[style]\ngroup = true\n", Path::new("/project")).unwrap_err();
+    assert_eq!(err.to_string(), "Unknown setting \"style.group\"");
+}
@@ -1,58 +1,311 @@
 
 extern crate petgraph;
 use petgraph::Graph;
+use petgraph::prelude::EdgeIndex;
 use petgraph::visit::NodeIndexable;
 use petgraph::visit::EdgeRef;
 
 use std::io::{self, Write};
-use std::path::Path;
-use std::fs::File;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::path::{Path, PathBuf};
+use std::ffi::OsString;
+use std::collections::{BTreeMap, HashMap};
 
 use file_node::FileNode;
+use path_utils;
+use hash_graph::{HashGraph, IncludeEdge};
+use writer::{node_identifiers, GraphWriter};
 
-// The simple dot writer in petgraph is not sufficient, so implement one here.
-pub fn write_dot_with_header(filename: &str,
-                             graph: &Graph<FileNode, bool>)
-                             -> Result<(), io::Error> {
+// Writes graphs in graphviz dot format.
+#[derive(Debug, Clone, Default)]
+pub struct DotWriter<'a> {
+    group_by_directory: bool,
+    edge_colors: HashMap<EdgeIndex, &'a str>,
+}
+
+impl<'a> DotWriter<'a> {
+    pub fn new() -> DotWriter<'a> {
+        DotWriter::default()
+    }
 
-    let out_path = Path::new(filename);
-    let mut dotfile = File::create(&out_path)?;
+    // Cluster files into nested subgraphs by directory.
+    pub fn group_by_directory(mut self, group_by_directory: bool) -> DotWriter<'a> {
+        self.group_by_directory = group_by_directory;
+        self
+    }
+
+    // Draw an edge in a color, e.g. "red".
+    pub fn edge_color(mut self, edge: EdgeIndex, color: &'a str) -> DotWriter<'a> {
+        self.edge_colors.insert(edge, color);
+        self
+    }
+}
+
+impl<'a> GraphWriter for DotWriter<'a> {
+    fn write_graph(&self, out: &mut dyn Write, graph: &HashGraph<FileNode>) -> Result<(), io::Error> {
+        write_dot_with_header(out, &graph.graph, self.group_by_directory, &self.edge_colors)
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// The simple dot writer in petgraph is not sufficient, so implement one here.
+// Edges listed in [edge_colors] are drawn in that color, e.g. "red".
+pub fn write_dot_with_header<W: Write + ?Sized>(out: &mut W,
+                                                graph: &Graph<FileNode, IncludeEdge>,
+                                                group_by_directory: bool,
+                                                edge_colors: &HashMap<EdgeIndex, &str>)
+                                                -> Result<(), io::Error> {
 
     // Define a directed graph graph type
-    writeln!(&mut dotfile, "digraph {{")?;
+    writeln!(out, "digraph {{")?;
 
     // Write layout header. This is the part that petgraph can't do yet.
-    writeln!(&mut dotfile, "    overlap=scale;")?;
-    writeln!(&mut dotfile, "    size=\"80,100\";")?;
-    // writeln!(&mut dotfile, "    ratio=\"fill\";")?;
-    // writeln!(&mut dotfile, "    ratio=\"compress\";")?;
-    writeln!(&mut dotfile, "    ratio=0.8;")?;
-    writeln!(&mut dotfile, "    fontsize=\"16\";")?;
-    writeln!(&mut dotfile, "    fontname=\"Helvetica\";")?;
-    writeln!(&mut dotfile, "    clusterrank=\"local\";")?;
+    writeln!(out, "    overlap=scale;")?;
+    writeln!(out, "    size=\"80,100\";")?;
+    // writeln!(out, "    ratio=\"fill\";")?;
+    // writeln!(out, "    ratio=\"compress\";")?;
+    writeln!(out, "    ratio=0.8;")?;
+    writeln!(out, "    fontsize=\"16\";")?;
+    writeln!(out, "    fontname=\"Helvetica\";")?;
+    writeln!(out, "    clusterrank=\"local\";")?;
+
+    // Nodes are named after their paths, so the same file keeps the same name
+    // when other files are added or removed.
+    let ids = node_identifiers(graph);
 
     // Write nodes with labels
     // Format:
-    //     6 [label="\"vector\""]
-    for node_idx in graph.node_indices() {
-        let integer_idx = graph.to_index(node_idx);
-        let node_ref = &graph[node_idx];
-        // println!("    {} [label={}]", integer_idx, node_ref);
-        writeln!(&mut dotfile, "    {} [label={}]", integer_idx, node_ref)?;
+    //     vector [label="vector"]
+    //     missing_h [label="missing.h", style=dashed, color=red, fontcolor=red]
+    if group_by_directory {
+        let mut cluster_count = 0;
+        let (root_dir, root_cluster) = build_clusters(graph);
+        for node_idx in &root_cluster.nodes {
+            writeln!(out, "    {} [{}]", ids[*node_idx], node_attributes(&graph[graph.from_index(*node_idx)]))?;
+        }
+        for (name, child) in &root_cluster.children {
+            let dir = root_dir.join(name);
+            write_cluster(out, graph, &ids, &dir, child, 1, &mut cluster_count)?;
+        }
+    } else {
+        for node_idx in graph.node_indices() {
+            let node_ref = &graph[node_idx];
+            writeln!(out, "    {} [{}]", ids[graph.to_index(node_idx)], node_attributes(node_ref))?;
+        }
     }
 
     // Write edges
     // Format:
-    //     1 -> 2
+    //     main_cpp -> a_h
+    //     main_cpp -> b_h [style=dashed, color=red]
     for edge in graph.edge_references() {
-        let src_idx = graph.to_index(edge.source());
-        let dst_idx = graph.to_index(edge.target());
-        // println!("    {} -> {}", src_idx, dst_idx);
-        writeln!(&mut dotfile, "    {} -> {}", src_idx, dst_idx)?;
+        let src_id = &ids[graph.to_index(edge.source())];
+        let dst_id = &ids[graph.to_index(edge.target())];
+
+        let mut attributes = Vec::new();
+        if !edge.weight().is_active {
+            // Inactive edges, e.g. includes in a disabled #if block.
+            attributes.push(String::from("style=dashed"));
+        }
+        if let Some(color) = edge_colors.get(&edge.id()) {
+            attributes.push(format!("color={}", color));
+        }
+
+        // println!("    {} -> {}", src_id, dst_id);
+        if attributes.is_empty() {
+            writeln!(out, "    {} -> {}", src_id, dst_id)?;
+        } else {
+            writeln!(out, "    {} -> {} [{}]", src_id, dst_id, attributes.join(", "))?;
+        }
     }
 
     // Close graph
-    writeln!(&mut dotfile, "}}")?;
+    writeln!(out, "}}")?;
 
     Ok(())
 }
+
+// Node label, plus a dashed red outline for includes that weren't found.
+fn node_attributes(node: &FileNode) -> String {
+    if node.is_resolved() {
+        format!("label={}", node)
+    } else {
+        format!("label={}, style=dashed, color=red, fontcolor=red", node)
+    }
+}
+
+// Render dot text into another format by piping it through graphviz,
+// e.g. 'dot -Tsvg'. Returns the rendered output.
+pub fn render_with_graphviz(dot_text: &[u8], format: &str) -> Result<Vec<u8>, io::Error> {
+    let mut child = Command::new("dot")
+        .arg(format!("-T{}", format))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Feed stdin from another thread, so a full stdout pipe can't block us.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = dot_text.to_vec();
+    let writer = thread::spawn(move || stdin.write_all(&input));
+
+    let output = child.wait_with_output()?;
+    writer.join().expect("stdin writer panicked")?;
+
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        return Err(io::Error::other(format!("dot exited with {}: {}", output.status, message.trim())));
+    }
+
+    Ok(output.stdout)
+}
+
+// -----------------------------------------------------------------------------
+
+// A directory in the cluster hierarchy, holding the nodes directly inside it.
+#[derive(Debug, Default)]
+struct Cluster {
+    nodes: Vec<usize>,
+    children: BTreeMap<OsString, Cluster>,
+}
+
+// Sort the graph nodes into a tree of directories. Returns the deepest
+// directory shared by all resolved files, and a cluster rooted above it.
+// Unresolved includes keep the relative path they were written with, so they
+// stay in the root cluster, outside of any directory.
+fn build_clusters(graph: &Graph<FileNode, IncludeEdge>) -> (PathBuf, Cluster) {
+
+    let node_dir = |node: &FileNode| {
+        node.path
+            .parent()
+            .filter(|dir| node.is_resolved() && !dir.as_os_str().is_empty())
+            .map(Path::to_path_buf)
+    };
+
+    // Find the common ancestor of all the node directories. Cluster the common
+    // directory itself, so its files are grouped too.
+    let node_dirs = graph.node_indices().filter_map(|idx| node_dir(&graph[idx])).collect::<Vec<_>>();
+    let common_dir = path_utils::common_ancestor(node_dirs.iter().map(PathBuf::as_path));
+    let root_dir = common_dir.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    let mut root = Cluster::default();
+    for node_idx in graph.node_indices() {
+        let mut cluster = &mut root;
+        if let Some(dir) = node_dir(&graph[node_idx]) {
+            if let Ok(relative_dir) = dir.strip_prefix(&root_dir) {
+                for component in relative_dir.components() {
+                    cluster = cluster.children
+                        .entry(component.as_os_str().to_os_string())
+                        .or_insert_with(Cluster::default);
+                }
+            }
+        }
+        cluster.nodes.push(graph.to_index(node_idx));
+    }
+
+    (root_dir, root)
+}
+
+// Write a directory as a nested 'subgraph cluster_N' block.
+fn write_cluster<W: Write + ?Sized>(out: &mut W,
+                                    graph: &Graph<FileNode, IncludeEdge>,
+                                    ids: &[String],
+                                    dir: &Path,
+                                    cluster: &Cluster,
+                                    depth: usize,
+                                    cluster_count: &mut usize)
+                                    -> Result<(), io::Error> {
+
+    let indent = "    ".repeat(depth);
+
+    writeln!(out, "{}subgraph cluster_{} {{", indent, cluster_count)?;
+    *cluster_count += 1;
+    writeln!(out,
+             "{}    label={:?};",
+             indent,
+             dir.file_name().unwrap_or(dir.as_os_str()))?;
+
+    for node_idx in &cluster.nodes {
+        writeln!(out,
+                 "{}    {} [{}]",
+                 indent,
+                 ids[*node_idx],
+                 node_attributes(&graph[graph.from_index(*node_idx)]))?;
+    }
+    for (name, child) in &cluster.children {
+        write_cluster(out, graph, ids, &dir.join(name), child, depth + 1, cluster_count)?;
+    }
+
+    writeln!(out, "{}}}", indent)
+}
+
+// -----------------------------------------------------------------------------
+
+#[test]
+fn test_build_clusters() {
+    let mut graph = Graph::<FileNode, IncludeEdge>::new();
+    let src = graph.add_node(FileNode::new("/project/src/main.cpp", false));
+    let inc = graph.add_node(FileNode::new("/project/src/util/util.h", false));
+    let sys = graph.add_node(FileNode::new("vector", true));
+    let missing = graph.add_node(FileNode::new("gen/missing.h", false));
+    let edge = |spelling: &str| {
+        IncludeEdge {
+            is_active: true,
+            line: 1,
+            spelling: String::from(spelling),
+        }
+    };
+    graph.add_edge(src, inc, edge("\"util/util.h\""));
+    graph.add_edge(src, sys, edge("<vector>"));
+    graph.add_edge(src, missing, edge("\"gen/missing.h\""));
+
+    let (root_dir, root) = build_clusters(&graph);
+    assert_eq!(root_dir, PathBuf::from("/project"));
+
+    // Unresolved includes stay outside of any cluster, even with a directory.
+    assert_eq!(root.nodes, vec![graph.to_index(sys), graph.to_index(missing)]);
+    assert_eq!(root.children.len(), 1);
+
+    let src_cluster = &root.children[&OsString::from("src")];
+    assert_eq!(src_cluster.nodes, vec![graph.to_index(src)]);
+    assert_eq!(src_cluster.children[&OsString::from("util")].nodes,
+               vec![graph.to_index(inc)]);
+}
+
+#[test]
+fn test_write_dot() {
+    let mut graph = Graph::<FileNode, IncludeEdge>::new();
+    let src = graph.add_node(FileNode::new("/project/main.cpp", false));
+    let inc = graph.add_node(FileNode::new("/project/a.h", false));
+    let edge = graph.add_edge(src,
+                              inc,
+                              IncludeEdge {
+                                  is_active: false,
+                                  line: 3,
+                                  spelling: String::from("\"a.h\""),
+                              });
+    let missing = graph.add_node(FileNode::new("missing.h", false));
+    graph.add_edge(src,
+                   missing,
+                   IncludeEdge {
+                       is_active: true,
+                       line: 4,
+                       spelling: String::from("\"missing.h\""),
+                   });
+
+    let mut edge_colors = HashMap::new();
+    edge_colors.insert(edge, "red");
+
+    let mut out = Vec::new();
+    write_dot_with_header(&mut out, &graph, false, &edge_colors).unwrap();
+    let text = String::from_utf8(out).unwrap();
+
+    assert!(text.starts_with("digraph {\n"));
+    assert!(text.contains("    main_cpp [label=\"main.cpp\"]\n"));
+    assert!(text.contains("    main_cpp -> a_h [style=dashed, color=red]\n"));
+    assert!(text.contains("    missing_h [label=\"missing.h\", style=dashed, color=red, fontcolor=red]\n"));
+    assert!(text.contains("    main_cpp -> missing_h\n"));
+    assert!(text.ends_with("}\n"));
+}
@@ -3,10 +3,69 @@ use std::fmt;
 use std::path::{Path, PathBuf};
 use std::ffi::OsStr;
 
-#[derive(Debug,Clone,PartialEq,Eq,Hash)]
+// Extensions of each kind of file, without the dot. Extensions are compared
+// in lower case.
+pub const HEADER_EXTENSIONS: [&str; 6] = ["h", "hh", "hpp", "hxx", "h++", "cuh"];
+pub const SOURCE_EXTENSIONS: [&str; 8] = ["c", "cc", "cpp", "cxx", "c++", "cu", "m", "mm"];
+pub const INLINE_EXTENSIONS: [&str; 6] = ["inl", "ipp", "tcc", "tpp", "txx", "inc"];
+pub const MODULE_INTERFACE_EXTENSIONS: [&str; 5] = ["ixx", "cppm", "ccm", "cxxm", "mpp"];
+
+// ----------------------------------------------------------------------------
+
+// What a file is used for, judged by its extension.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum FileKind {
+    Header, // Also files without an extension, e.g. <vector>.
+    Source, // Compiled on its own.
+    Inline, // Template or inline definitions, included by a header.
+    ModuleInterface, // C++20 module interface unit.
+}
+
+impl FileKind {
+    // The kind of files with [ext], or None if it isn't a known C, C++,
+    // CUDA or Objective-C extension.
+    pub fn from_extension(ext: &str) -> Option<FileKind> {
+        let ext = ext.to_lowercase();
+        let ext = ext.as_str();
+        if HEADER_EXTENSIONS.contains(&ext) {
+            Some(FileKind::Header)
+        } else if SOURCE_EXTENSIONS.contains(&ext) {
+            Some(FileKind::Source)
+        } else if INLINE_EXTENSIONS.contains(&ext) {
+            Some(FileKind::Inline)
+        } else if MODULE_INTERFACE_EXTENSIONS.contains(&ext) {
+            Some(FileKind::ModuleInterface)
+        } else {
+            None
+        }
+    }
+
+    // The name used in graph output, e.g. "module-interface".
+    pub fn name(self) -> &'static str {
+        match self {
+            FileKind::Header => "header",
+            FileKind::Source => "source",
+            FileKind::Inline => "inline",
+            FileKind::ModuleInterface => "module-interface",
+        }
+    }
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug,Clone,PartialEq,Eq,PartialOrd,Ord,Hash)]
 pub struct FileNode {
     pub path: PathBuf, // Can use is_absolute() and is_relative() to check status.
     pub is_system: bool,
+    // For unresolved "" includes, the directory of the including file. A
+    // missing "config.h" in one directory isn't the one missing from another.
+    pub includer_dir: Option<PathBuf>,
 }
 
 impl FileNode {
@@ -14,6 +73,7 @@ impl FileNode {
         FileNode {
             path: PathBuf::from(name),
             is_system: is_sys,
+            includer_dir: None,
         }
     }
 
@@ -21,6 +81,49 @@ impl FileNode {
         FileNode {
             path: PathBuf::from(path),
             is_system: is_sys,
+            includer_dir: None,
+        }
+    }
+
+    // True if the file was found on disk. Unresolved includes keep the
+    // relative path they were written with.
+    pub fn is_resolved(&self) -> bool {
+        self.path.is_absolute()
+    }
+
+    // What the file is used for. Files with no extension, or one that isn't
+    // known, are taken to be headers, since they can only be included.
+    pub fn kind(&self) -> FileKind {
+        self.path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(FileKind::from_extension)
+            .unwrap_or(FileKind::Header)
+    }
+
+    // Return the module this file belongs to, by stripping source and header
+    // extensions. E.g. 'src/foo.cpp', 'src/foo.h' and 'src/foo.inl' all
+    // become 'src/foo'.
+    pub fn to_module(&self) -> FileNode {
+        let is_module_file = self.path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(FileKind::from_extension)
+            .is_some();
+
+        if is_module_file {
+            FileNode { path: self.path.with_extension(""), ..self.clone() }
+        } else {
+            self.clone()
+        }
+    }
+
+    // Return the directory containing this file. Unresolved includes with no
+    // directory component (e.g. <vector>) are left as they are.
+    pub fn to_directory(&self) -> FileNode {
+        match self.path.parent() {
+            Some(dir) if dir.file_name().is_some() => FileNode { path: dir.to_path_buf(), ..self.clone() },
+            _ => self.clone(),
         }
     }
 }
@@ -35,3 +138,23 @@ impl fmt::Display for FileNode {
                    .unwrap_or(OsStr::new("[error getting filename]")))
     }
 }
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_file_kind() {
+    let kind = |name: &str| FileNode::new(name, false).kind();
+
+    assert_eq!(kind("/src/foo.h"), FileKind::Header);
+    assert_eq!(kind("/src/kernel.cuh"), FileKind::Header);
+    assert_eq!(kind("vector"), FileKind::Header);
+    assert_eq!(kind("/src/FOO.CPP"), FileKind::Source);
+    assert_eq!(kind("/src/view.mm"), FileKind::Source);
+    assert_eq!(kind("/src/foo.inl"), FileKind::Inline);
+    assert_eq!(kind("/src/list.tcc"), FileKind::Inline);
+    assert_eq!(kind("/src/math.ixx"), FileKind::ModuleInterface);
+    assert_eq!(FileKind::ModuleInterface.to_string(), "module-interface");
+
+    assert_eq!(FileNode::new("/src/foo.ipp", false).to_module().path, PathBuf::from("/src/foo"));
+    assert_eq!(FileNode::new("/src/foo.txt", false).to_module().path, PathBuf::from("/src/foo.txt"));
+}
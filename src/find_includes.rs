@@ -1,69 +1,421 @@
-
+use std::fmt;
 use std::io::{self, Read};
-use std::fs::File;
+use std::fs::{self, File};
 use std::path::{Path, PathBuf};
-use std::ffi::OsString;
-use std::error::Error;
-use std::collections::HashSet;
+use std::str::FromStr;
+use std::collections::{HashMap, HashSet, VecDeque};
 
-use walkdir::WalkDir;
+use ignore::WalkBuilder;
 use regex::Regex;
 use itertools::Itertools;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use rayon::prelude::*;
+
+use compile_commands::CompileCommand;
+use file_node::{self, FileNode};
+use hash_graph::{HashGraph, IncludeEdge};
+use lexer;
+use path_filter::PathFilter;
+use path_utils::{self, IncludeKind, SearchPaths};
+use preprocessor::{MacroMap, Preprocessor};
+use scan_cache::{FileStamp, ScanCache};
+
+// ----------------------------------------------------------------------------
+
+// How much of an extensionless file is read to decide whether to scan it.
+const SNIFF_LENGTH: u64 = 4096;
+
+/// File extensions scanned when none are given: every kind of file known to
+/// FileKind.
+pub fn default_extensions() -> Vec<&'static str> {
+    file_node::HEADER_EXTENSIONS.iter()
+        .chain(file_node::SOURCE_EXTENSIONS.iter())
+        .chain(file_node::INLINE_EXTENSIONS.iter())
+        .chain(file_node::MODULE_INTERFACE_EXTENSIONS.iter())
+        .cloned()
+        .collect()
+}
+
+/// Which includes to follow, by the way they quote the file name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteTypes {
+    Both, // All includes.
+    Angle, // Only "system" includes (<>).
+    Quote, // Only "user" includes ("").
+}
+
+impl QuoteTypes {
+    /// Names accepted by from_str(), e.g. for command line help.
+    pub fn variants() -> [&'static str; 3] {
+        ["both", "angle", "quote"]
+    }
+
+    fn parse_user_includes(self) -> bool {
+        self != QuoteTypes::Angle
+    }
+
+    fn parse_system_includes(self) -> bool {
+        self != QuoteTypes::Quote
+    }
+}
 
-use file_node::FileNode;
-use hash_graph::HashGraph;
-use super::*;
+impl FromStr for QuoteTypes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<QuoteTypes, String> {
+        match s.to_lowercase().as_str() {
+            "both" => Ok(QuoteTypes::Both),
+            "angle" => Ok(QuoteTypes::Angle),
+            "quote" => Ok(QuoteTypes::Quote),
+            _ => Err(format!("Unknown quote type: {}", s)),
+        }
+    }
+}
 
 // ----------------------------------------------------------------------------
 
-// Convert a relative include path (e.g. <Windows.h>) into an absolute path.
-fn find_absolute_include_path(include: &FileNode,
-                              parent_file: &Path,
-                              system_search_paths: &[PathBuf])
-                              -> FileNode {
+/// Scans source files into an include graph. Options are set with the builder
+/// methods, e.g.
+///
+/// ```no_run
+/// # use std::path::Path;
+/// # use include2dot::{QuoteTypes, Scanner, SearchPaths};
+/// # let search_paths = SearchPaths::default();
+/// let graph = Scanner::new()
+///     .search_paths(search_paths)
+///     .quote_types(QuoteTypes::Quote)
+///     .scan_tree(Path::new("src"))
+///     .graph;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Scanner {
+    search_paths: SearchPaths,
+    extensions: HashSet<String>, // In lower case.
+    quote_types: QuoteTypes,
+    exclude_regex: Option<Regex>,
+    path_filter: PathFilter,
+    defines: MacroMap,
+    keep_conditional: bool,
+    sniff_content: bool,
+    respect_ignore_files: bool,
+    scan_hidden: bool,
+    follow_links: bool,
+    jobs: usize,
+    cache_file: Option<PathBuf>,
+}
 
-    let local_dir = parent_file.parent().unwrap(); // strip the file name
+impl Default for Scanner {
+    fn default() -> Scanner {
+        Scanner::new()
+    }
+}
 
-    let normalized_path = path_utils::normalize_path_separators(&include.path);
+impl Scanner {
+    /// A scanner that follows all includes in files with the default extensions.
+    pub fn new() -> Scanner {
+        Scanner {
+            search_paths: SearchPaths::default(),
+            extensions: default_extensions().into_iter().map(String::from).collect(),
+            quote_types: QuoteTypes::Both,
+            exclude_regex: None,
+            path_filter: PathFilter::default(),
+            defines: MacroMap::new(),
+            keep_conditional: false,
+            sniff_content: false,
+            respect_ignore_files: false,
+            scan_hidden: false,
+            follow_links: false,
+            jobs: 0,
+            cache_file: None,
+        }
+    }
 
-    match path_utils::convert_to_absolute_path(&normalized_path, local_dir, system_search_paths) {
-        None => {
+    /// Paths to search for included files.
+    pub fn search_paths(mut self, search_paths: SearchPaths) -> Scanner {
+        self.search_paths = search_paths;
+        self
+    }
+
+    /// File extensions to scan when walking a tree, without the dot. Case
+    /// doesn't matter, so "h" also scans "FOO.H".
+    pub fn extensions<I, S>(mut self, extensions: I) -> Scanner
+        where I: IntoIterator<Item = S>,
+              S: AsRef<str>
+    {
+        self.extensions = extensions.into_iter().map(|ext| ext.as_ref().to_lowercase()).collect();
+        self
+    }
+
+    /// Which kinds of include to follow.
+    pub fn quote_types(mut self, quote_types: QuoteTypes) -> Scanner {
+        self.quote_types = quote_types;
+        self
+    }
+
+    /// Skip files and includes whose names match this expression.
+    pub fn exclude(mut self, exclude_regex: Regex) -> Scanner {
+        self.exclude_regex = Some(exclude_regex);
+        self
+    }
+
+    /// Gitignore-style globs of the files to scan when walking a tree.
+    /// Excluded directories are skipped without being read.
+    pub fn path_filter(mut self, path_filter: PathFilter) -> Scanner {
+        self.path_filter = path_filter;
+        self
+    }
+
+    /// Macros used to evaluate #if blocks.
+    pub fn defines(mut self, defines: MacroMap) -> Scanner {
+        self.defines = defines;
+        self
+    }
+
+    /// Keep includes from inactive #if blocks, as inactive edges.
+    pub fn keep_conditional(mut self, keep_conditional: bool) -> Scanner {
+        self.keep_conditional = keep_conditional;
+        self
+    }
 
-            println!("Unable to locate {:?}", &include.path);
-            println!("  In local directory {}", local_dir.display());
-            println!("  Included from file {:?}\n", parent_file.display());
-            include.clone()
+    /// Also scan files without an extension when they look like C or C++,
+    /// e.g. the headers of a standard library.
+    pub fn sniff_content(mut self, sniff_content: bool) -> Scanner {
+        self.sniff_content = sniff_content;
+        self
+    }
+
+    /// Skip the files listed in .gitignore and .ignore files, and in
+    /// .git/info/exclude, when walking a tree. The user's global gitignore is
+    /// left out, so scans don't depend on who runs them.
+    pub fn respect_ignore_files(mut self, respect_ignore_files: bool) -> Scanner {
+        self.respect_ignore_files = respect_ignore_files;
+        self
+    }
+
+    /// Also walk hidden directories, like '.git' or '.cache'.
+    pub fn scan_hidden(mut self, scan_hidden: bool) -> Scanner {
+        self.scan_hidden = scan_hidden;
+        self
+    }
+
+    /// Follow symbolic links when walking a tree. Links back to a directory
+    /// being walked are reported and skipped, and files reached through more
+    /// than one link are only scanned once.
+    pub fn follow_links(mut self, follow_links: bool) -> Scanner {
+        self.follow_links = follow_links;
+        self
+    }
+
+    /// Number of threads used to scan files. 0 uses one per CPU.
+    pub fn jobs(mut self, jobs: usize) -> Scanner {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Save the includes found by scan_tree() to this file, and reuse them for
+    /// files that haven't changed since the last scan.
+    pub fn cache_file(mut self, cache_file: PathBuf) -> Scanner {
+        self.cache_file = Some(cache_file);
+        self
+    }
+
+    // The saved scan results, or an empty cache if there aren't any usable ones.
+    fn load_cache(&self, errors: &mut Vec<ScanError>) -> ScanCache {
+        match self.cache_file {
+            Some(ref path) if path.exists() => {
+                ScanCache::load(path, &self.defines).unwrap_or_else(|err| {
+                    errors.push(ScanError::new(Some(path), format!("Ignoring scan cache: {}", err)));
+                    ScanCache::new(&self.defines)
+                })
+            }
+            _ => ScanCache::new(&self.defines),
         }
-        Some(path_buf) => FileNode::from_path(&path_buf, include.is_system),
+    }
+
+    // A thread pool of the requested size, or None to use the global pool.
+    fn thread_pool(&self, errors: &mut Vec<ScanError>) -> Option<ThreadPool> {
+        if self.jobs == 0 {
+            return None;
+        }
+        ThreadPoolBuilder::new()
+            .num_threads(self.jobs)
+            .build()
+            .map_err(|err| {
+                let message = format!("Unable to start {} scanning threads: {}", self.jobs, err);
+                errors.push(ScanError::new(None, message));
+            })
+            .ok()
+    }
+
+    /// Scan every matching file under `root_dir`.
+    pub fn scan_tree(&self, root_dir: &Path) -> ScanResult {
+        find_includes_in_tree(self, root_dir)
+    }
+
+    /// Scan the translation units of a compilation database, following their
+    /// includes with each unit's own search paths and macros.
+    pub fn scan_compile_commands(&self, commands: &[CompileCommand]) -> ScanResult {
+        find_includes_in_compile_commands(self, commands)
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+/// An include that wasn't found in any of the directories searched. It is
+/// still added to the graph, as a node with the relative path as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnresolvedInclude {
+    pub file: PathBuf, // The including file.
+    pub line: usize,
+    pub column: usize,
+    pub spelling: String, // The include as written, e.g. "\"foo.h\"".
+    pub searched: Vec<PathBuf>, // Directories searched, in lookup order.
+}
+
+/// A problem that stopped part of a scan, e.g. a file that couldn't be read.
+/// The rest of the scan carries on without it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanError {
+    pub path: Option<PathBuf>, // The file or directory involved, if there is one.
+    pub message: String,
+}
+
+impl ScanError {
+    fn new(path: Option<&Path>, message: String) -> ScanError {
+        ScanError {
+            path: path.map(Path::to_path_buf),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.path {
+            Some(ref path) => write!(f, "{}: {}", path.display(), self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// The include graph found by a Scanner, with the includes it couldn't resolve
+/// and the problems it ran into.
+#[derive(Debug)]
+pub struct ScanResult {
+    pub graph: HashGraph<FileNode>,
+    pub unresolved: Vec<UnresolvedInclude>, // Sorted by file and line, without duplicates.
+    pub errors: Vec<ScanError>, // In the order they were found.
+}
+
+impl ScanResult {
+    fn new(graph: HashGraph<FileNode>,
+           mut unresolved: Vec<UnresolvedInclude>,
+           errors: Vec<ScanError>)
+           -> ScanResult {
+        // Sort the graph so node indices, and the output written from them,
+        // are the same on every run.
+        let graph = graph.sorted();
+
+        // Headers shared by several translation units are resolved once per unit.
+        unresolved.sort_by(|a, b| (&a.file, a.line, a.column).cmp(&(&b.file, b.line, b.column)));
+        unresolved.dedup_by(|a, b| (&a.file, a.line, a.column) == (&b.file, b.line, b.column));
+
+        ScanResult {
+            graph,
+            unresolved,
+            errors,
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// Convert a relative include path (e.g. <Windows.h>) into an absolute path.
+fn find_absolute_include_path(include: &Include,
+                              parent_file: &Path,
+                              search_paths: &SearchPaths)
+                              -> Result<FileNode, UnresolvedInclude> {
+    let normalized_path = path_utils::normalize_path_separators(&include.file.path);
+
+    let kind = if include.is_next {
+        IncludeKind::Next
+    } else if include.file.is_system {
+        IncludeKind::Angle
+    } else {
+        IncludeKind::Quote
+    };
+
+    match path_utils::convert_to_absolute_path(&normalized_path, parent_file, kind, search_paths) {
+        None => {
+            Err(UnresolvedInclude {
+                file: parent_file.to_path_buf(),
+                line: include.line,
+                column: include.column,
+                spelling: include.spelling(),
+                searched: path_utils::search_directories(parent_file, kind, search_paths),
+            })
+        }
+        Some(path_buf) => Ok(FileNode::from_path(&path_buf, include.file.is_system)),
     }
 }
 
 // -----------------------------------------------------------------------------
 
+// A single #include directive found while scanning a file.
+#[derive(Debug, Clone)]
+pub struct Include {
+    pub file: FileNode,
+    pub is_active: bool, // False if the include is inside an inactive #if block.
+    pub is_next: bool, // True for #include_next.
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Include {
+    // The include as written, e.g. "<vector>" or "\"foo.h\"".
+    pub fn spelling(&self) -> String {
+        if self.file.is_system {
+            format!("<{}>", self.file.path.display())
+        } else {
+            format!("\"{}\"", self.file.path.display())
+        }
+    }
+
+    // The graph edge for this include.
+    pub fn to_edge(&self) -> IncludeEdge {
+        IncludeEdge {
+            is_active: self.is_active,
+            line: self.line,
+            spelling: self.spelling(),
+        }
+    }
+}
+
 // Return a list of #include statements found in the file
-fn scan_file_for_includes(file: &Path) -> Result<Vec<FileNode>, io::Error> {
+fn scan_file_for_includes(file: &Path, defines: &MacroMap) -> Result<Vec<Include>, io::Error> {
     let mut f = File::open(file)?;
     let mut text = String::new();
     f.read_to_string(&mut text)?;
 
     let mut includes = Vec::new();
+    let mut preprocessor = Preprocessor::new(defines);
 
-    // Use a regex to search for '#include ...' lines.
-    // The second (...) capture group isolates just the text, not the "" or <> symbols.
-    lazy_static! {
-    // Notes:
-    // (?m:^[[:blank:]]*) => empty space at line start, multi-line mode, non-capturing group.
-        static ref RE: Regex =
-        Regex::new(r##"(?m:^[[:blank:]]*)#[[:blank:]]*include[[:blank:]]*([<"])(.*)[>"]"##).unwrap();
-    }
-
-    // cap.at(1) is an angle brace or double quote, to determine user or system include.
-    // cap.at(2) is the include file name.
-    for cap in RE.captures_iter(&text) {
-        let is_system_include = cap.get(1).map_or(false, |sym| sym.as_str() == "<");
+    for directive in lexer::scan_directives(&text) {
+        let is_next = directive.name == "include_next";
+        if directive.name != "include" && !is_next {
+            preprocessor.process_directive(&directive.name, &directive.args);
+            continue;
+        }
 
-        if let Some(include_name) = cap.get(2) {
-            includes.push(FileNode::new(include_name.as_str(), is_system_include));
+        // Computed includes (e.g. #include MACRO) can't be resolved, so skip them.
+        if let Some((include_name, is_system_include)) = directive.include_name() {
+            includes.push(Include {
+                file: FileNode::new(include_name, is_system_include),
+                is_active: preprocessor.is_active(),
+                is_next,
+                line: directive.line,
+                column: directive.column,
+            });
         }
     }
 
@@ -72,60 +424,265 @@ fn scan_file_for_includes(file: &Path) -> Result<Vec<FileNode>, io::Error> {
     Ok(includes)
 }
 
+// True if the start of [file] looks like C or C++: it has no NUL bytes, and
+// either has a preprocessor directive or an Emacs "-*- C++ -*-" mode line.
+fn looks_like_source(file: &Path) -> bool {
+    if !file.is_file() {
+        return false;
+    }
+    let mut bytes = Vec::new();
+    if File::open(file).and_then(|f| f.take(SNIFF_LENGTH).read_to_end(&mut bytes)).is_err() ||
+       bytes.contains(&0) {
+        return false;
+    }
+
+    let text = String::from_utf8_lossy(&bytes);
+    text.contains("-*- C++ -*-") || text.contains("-*- c++ -*-") ||
+    lexer::scan_directives(&text)
+        .iter()
+        .any(|directive| ["include", "pragma", "ifndef", "define"].contains(&directive.name.as_str()))
+}
+
+// Filter the includes found in a file by type and exclude regex, and convert
+// them to absolute paths. Returns each include with its graph edge. Includes
+// that can't be found keep their relative path, and are added to [unresolved].
+// Unresolved "" includes also remember the including directory, since they
+// would have been found there.
+fn resolve_includes(scanner: &Scanner,
+                    includes: &[Include],
+                    parent_file: &Path,
+                    search_paths: &SearchPaths,
+                    unresolved: &mut Vec<UnresolvedInclude>)
+                    -> Vec<(FileNode, IncludeEdge)> {
+    includes.iter()
+        .filter(|inc| inc.is_active || scanner.keep_conditional)
+        .filter(|inc| {
+            (!inc.file.is_system && scanner.quote_types.parse_user_includes()) ||
+            (inc.file.is_system && scanner.quote_types.parse_system_includes())
+        })
+        .filter(|inc| !path_utils::name_matches_regex(&scanner.exclude_regex, &inc.file.path))
+        .map(|inc| {
+            let file = find_absolute_include_path(inc, parent_file, search_paths)
+                .unwrap_or_else(|report| {
+                    unresolved.push(report);
+                    let includer_dir =
+                        if inc.file.is_system { None } else { parent_file.parent().map(Path::to_path_buf) };
+                    FileNode { includer_dir, ..inc.file.clone() }
+                });
+            (file, inc.to_edge())
+        })
+        .collect()
+}
+
 // -----------------------------------------------------------------------------
 
 // Core include searching loop
-pub fn find_includes_in_tree(root_dir: &Path,
-                             search_paths: &[PathBuf],
-                             extensions: &HashSet<OsString>,
-                             parse_user_includes: bool,
-                             parse_system_includes: bool,
-                             exclude_regex: &Option<Regex>)
-                             -> HashGraph<FileNode> {
-    // Collect all the files to scan in a HashSet
-    // Note: is_hidden() is currently hiding paths that start with './', so don't use it yet.
-    let input_queue = WalkDir::new(root_dir).into_iter()
-        //.filter_entry(|e| !path_utils::is_hidden(e))
+fn find_includes_in_tree(scanner: &Scanner, root_dir: &Path) -> ScanResult {
+    // Walk from the absolute root, so scanned files have absolute paths just
+    // like the resolved includes.
+    let root_dir = fs::canonicalize(root_dir).unwrap_or_else(|_| root_dir.to_path_buf());
+
+    // Path globs are matched relative to the root.
+    let relative = |root_dir: &Path, path: &Path| path.strip_prefix(root_dir).unwrap_or(path).to_path_buf();
+
+    // Skip hidden and excluded directories without reading them. The filter
+    // outlives this call, so it gets its own copy of the globs.
+    let walk_root = root_dir.clone();
+    let path_filter = scanner.path_filter.clone();
+    let scan_hidden = scanner.scan_hidden;
+    let skip_dir = move |entry: &ignore::DirEntry| {
+        let is_dir = entry.file_type().map(|file_type| file_type.is_dir()) == Some(true);
+        is_dir && entry.depth() > 0 &&
+        ((!scan_hidden && path_utils::is_hidden(entry)) ||
+         path_filter.excludes_dir(&relative(&walk_root, entry.path())))
+    };
+
+    // Collect all the files to scan. They're sorted so that the graph is built
+    // in the same order on every run.
+    let mut errors = Vec::new();
+    let mut input_files = WalkBuilder::new(&root_dir)
+        .standard_filters(false)
+        .git_ignore(scanner.respect_ignore_files)
+        .git_exclude(scanner.respect_ignore_files)
+        .ignore(scanner.respect_ignore_files)
+        .parents(scanner.respect_ignore_files)
+        .require_git(false)
+        .follow_links(scanner.follow_links)
+        .filter_entry(move |entry| !skip_dir(entry))
+        .build()
         .filter_map(|entry| match entry {
-            Err(what) => {println!("Error reading directory: {}", what.description()); None},
+            Err(what) => {
+                errors.push(ScanError::new(None, format!("Unable to read directory: {}", what)));
+                None
+            }
             Ok(val) => Some(val),
         })
-        .map(|entry| PathBuf::from(entry.path()))
-        .filter(|path| path.extension().map_or(false, |ext| extensions.contains(ext)))
-        .filter(|path| !path_utils::filename_matches_regex(exclude_regex, path))
-        .collect::<HashSet<_>>();
+        .map(|entry| entry.into_path())
+        // The same file can be linked from several places.
+        .map(|path| if scanner.follow_links { fs::canonicalize(&path).unwrap_or(path) } else { path })
+        .filter(|path| !path_utils::filename_matches_regex(&scanner.exclude_regex, path))
+        .filter(|path| scanner.path_filter.includes_file(&relative(&root_dir, path)))
+        .filter(|path| match path.extension() {
+            Some(ext) => scanner.extensions.contains(&ext.to_string_lossy().to_lowercase()),
+            None => scanner.sniff_content && looks_like_source(path),
+        })
+        .collect::<Vec<_>>();
+    input_files.sort();
+    input_files.dedup();
+
+    // Files that haven't changed since the last run reuse their cached includes.
+    let cache = scanner.load_cache(&mut errors);
+
+    // Scan and resolve the files in parallel. Each file gets its own list of
+    // edges and unresolved includes, and the results keep the input order.
+    let scan_file = |parent_file: &PathBuf| {
+        // Stamp the file before reading it, so a change made during the scan
+        // is picked up next time.
+        let stamp = FileStamp::of(parent_file);
+        let includes = match stamp.and_then(|stamp| cache.get(parent_file, stamp)) {
+            Some(includes) => Ok(includes.to_vec()),
+            None => scan_file_for_includes(parent_file, &scanner.defines),
+        };
+
+        includes.map(|includes| {
+            // Convert relative includes to absolute includes
+            let mut file_unresolved = Vec::new();
+            let resolved = resolve_includes(scanner,
+                                            &includes,
+                                            parent_file,
+                                            &scanner.search_paths,
+                                            &mut file_unresolved);
+            (stamp, includes, resolved, file_unresolved)
+        })
+    };
+    let scanned = match scanner.thread_pool(&mut errors) {
+        Some(pool) => pool.install(|| input_files.par_iter().map(&scan_file).collect::<Vec<_>>()),
+        None => input_files.par_iter().map(&scan_file).collect::<Vec<_>>(),
+    };
 
     // Graph of all the tracked files
     let mut hash_graph = HashGraph::<FileNode>::new();
-
-    for path_buf in input_queue {
-        let parent_file = path_buf.as_path();
-        let includes_result = scan_file_for_includes(parent_file);
-        match includes_result {
-            Ok(includes) => {
-
-                // Convert relative includes to absolute includes
-                includes.iter()
-                    .filter(|inc| {
-                        (!inc.is_system && parse_user_includes) ||
-                        (inc.is_system && parse_system_includes)
-                    })
-                    .filter(|inc| !path_utils::name_matches_regex(exclude_regex, &inc.path))
-                    .map(|inc| find_absolute_include_path(inc, parent_file, search_paths))
-                    .foreach(|inc| {
-                        // Add an edge to the graph
+    let mut unresolved = Vec::new();
+    // Files from other trees stay in the cache, so scanning several trees
+    // with one cache file doesn't throw away the others' results.
+    let mut new_cache = cache;
+
+    // Merge the results on this thread, in file order, so node indices don't
+    // depend on thread scheduling.
+    for (parent_file, result) in input_files.iter().zip(scanned) {
+        match result {
+            Ok((stamp, includes, resolved, file_unresolved)) => {
+                if let Some(stamp) = stamp {
+                    new_cache.insert(parent_file.clone(), stamp, includes);
+                }
+
+                resolved.into_iter()
+                    .foreach(|(inc, edge)| {
+                        // Add an edge to the graph. Conditional includes get an inactive edge.
                         let src_node = FileNode::from_path(parent_file, false);
-                        let dst_node = FileNode::from_path(&inc.path, inc.is_system);
-                        hash_graph.add_edge(src_node, dst_node);
+                        hash_graph.add_edge(src_node, inc, edge);
                     });
+                unresolved.extend(file_unresolved);
             }
             Err(err) => {
-                println!("Unable to process file {:?}: {}", parent_file, err);
+                errors.push(ScanError::new(Some(parent_file), format!("Unable to scan file: {}", err)));
+            }
+        }
+    }
+
+    // Deleted files drop out of the cache.
+    if let Some(ref cache_file) = scanner.cache_file {
+        new_cache.remove_missing_files();
+        if let Err(err) = new_cache.save(cache_file) {
+            errors.push(ScanError::new(Some(cache_file), format!("Unable to save scan cache: {}", err)));
+        }
+    }
+
+    ScanResult::new(hash_graph, unresolved, errors)
+}
+
+// Include searching loop driven by a compilation database. Only the compiled
+// files are scanned directly. Their includes are then followed using each
+// file's own search paths and macro definitions.
+fn find_includes_in_compile_commands(scanner: &Scanner, commands: &[CompileCommand]) -> ScanResult {
+
+    // Graph of all the tracked files
+    let mut hash_graph = HashGraph::<FileNode>::new();
+    let mut unresolved = Vec::new();
+    let mut errors = Vec::new();
+
+    // Headers are shared between translation units, so cache the scan results.
+    // Conditional blocks depend on the macros, so those are part of the key.
+    let mut scan_cache = HashMap::<(PathBuf, String), Vec<Include>>::new();
+
+    for command in commands {
+        if path_utils::filename_matches_regex(&scanner.exclude_regex, &command.file) {
+            continue;
+        }
+        if !command.file.is_file() {
+            errors.push(ScanError::new(Some(&command.file), String::from("Unable to scan file: not found")));
+            continue;
+        }
+
+        // Search the translation unit's own paths first. Macros given on the
+        // command line override the ones from the database.
+        let mut unit_search_paths = command.search_paths();
+        unit_search_paths.extend(&scanner.search_paths);
+
+        let mut unit_defines = command.defines.clone();
+        unit_defines.extend(scanner.defines.iter().map(|(name, value)| (name.clone(), value.clone())));
+        let mut defines_list = unit_defines.iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect::<Vec<_>>();
+        defines_list.sort();
+        let defines_key = defines_list.join("\n");
+
+        // Run a breadth-first traversal of the active includes.
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(FileNode::from_path(&command.file, false));
+
+        while let Some(parent_node) = queue.pop_front() {
+            // Unresolved includes can't be scanned. Their relative paths would
+            // otherwise be opened from the current directory.
+            if !parent_node.is_resolved() || !parent_node.path.is_file() ||
+               !visited.insert(parent_node.path.clone()) {
+                continue;
+            }
+
+            let cache_key = (parent_node.path.clone(), defines_key.clone());
+            if !scan_cache.contains_key(&cache_key) {
+                match scan_file_for_includes(&parent_node.path, &unit_defines) {
+                    Ok(includes) => {
+                        scan_cache.insert(cache_key.clone(), includes);
+                    }
+                    Err(err) => {
+                        let message = format!("Unable to scan file: {}", err);
+                        errors.push(ScanError::new(Some(&parent_node.path), message));
+                        continue;
+                    }
+                }
+            }
+
+            let resolved = resolve_includes(scanner,
+                                            &scan_cache[&cache_key],
+                                            &parent_node.path,
+                                            &unit_search_paths,
+                                            &mut unresolved);
+
+            for (inc, edge) in resolved {
+                let is_active = edge.is_active;
+                hash_graph.add_edge(parent_node.clone(), inc.clone(), edge);
+
+                // Includes in inactive blocks are drawn, but not followed.
+                if is_active {
+                    queue.push_back(inc);
+                }
             }
         }
     }
 
-    hash_graph
+    ScanResult::new(hash_graph, unresolved, errors)
 }
 
 // -----------------------------------------------------------------------------
@@ -134,24 +691,23 @@ pub fn find_includes_in_tree(root_dir: &Path,
 mod test {
 
     use super::*;
+    use std::env;
+    use compile_commands;
+    use petgraph::prelude::NodeIndex;
 
     #[test]
     fn parse_simple() {
         let testdata_dir = env::current_dir().unwrap().join("testdata").join("simple");
 
-        let mut search_paths = Vec::new();
-        search_paths.push(PathBuf::from(&testdata_dir));
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
 
-        let mut extensions = HashSet::new();
-        extensions.insert(OsString::from("h"));
-        extensions.insert(OsString::from("cpp"));
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"])
+            .quote_types(QuoteTypes::Quote);
 
-        let hash_graph = find_includes_in_tree(&testdata_dir,
-                                               &search_paths,
-                                               &extensions,
-                                               true,
-                                               false,
-                                               &None);
+        let hash_graph = scanner.scan_tree(&testdata_dir).graph;
 
         assert_eq!(hash_graph.graph.node_count(), 4);
     }
@@ -160,19 +716,15 @@ mod test {
     fn parse_user_includes() {
         let testdata_dir = env::current_dir().unwrap().join("testdata").join("complex");
 
-        let mut search_paths = Vec::new();
-        search_paths.push(PathBuf::from(&testdata_dir));
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
 
-        let mut extensions = HashSet::new();
-        extensions.insert(OsString::from("h"));
-        extensions.insert(OsString::from("cpp"));
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"])
+            .quote_types(QuoteTypes::Quote);
 
-        let hash_graph = find_includes_in_tree(&testdata_dir,
-                                               &search_paths,
-                                               &extensions,
-                                               true,
-                                               false,
-                                               &None);
+        let hash_graph = scanner.scan_tree(&testdata_dir).graph;
 
         assert_eq!(hash_graph.graph.node_count(), 7);
     }
@@ -181,32 +733,194 @@ mod test {
     fn parse_all_includes() {
         let testdata_dir = env::current_dir().unwrap().join("testdata").join("complex");
 
-        let mut search_paths = Vec::new();
-        search_paths.push(PathBuf::from(&testdata_dir));
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
 
-        let mut extensions = HashSet::new();
-        extensions.insert(OsString::from("h"));
-        extensions.insert(OsString::from("cpp"));
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"]);
 
-        let hash_graph =
-            find_includes_in_tree(&testdata_dir, &search_paths, &extensions, true, true, &None);
+        let hash_graph = scanner.scan_tree(&testdata_dir).graph;
 
         assert_eq!(hash_graph.graph.node_count(), 12);
     }
 
+    #[test]
+    fn match_extensions_in_any_case() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("case");
+
+        // Main.CPP includes Util.HPP, which includes a missing header.
+        for extensions in &[vec!["cpp", "hpp"], vec!["Cpp", "HPP"]] {
+            let graph = Scanner::new().extensions(extensions).scan_tree(&testdata_dir).graph;
+            assert_eq!(graph.graph.edge_count(), 2);
+        }
+        assert_eq!(Scanner::new().scan_tree(&testdata_dir).graph.graph.edge_count(), 2);
+    }
+
+    #[test]
+    fn sniff_extensionless_headers() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("sniff");
+        let names = |scanner: Scanner| {
+            let graph = scanner.scan_tree(&testdata_dir).graph;
+            graph.nodes()
+                .map(|node| format!("{} {}", node.path.file_name().unwrap().to_str().unwrap(), node.kind()))
+                .collect::<Vec<_>>()
+        };
+
+        // Without sniffing, config is only found as an include of main.cpp.
+        assert_eq!(names(Scanner::new()), vec!["config header", "main.cpp source"]);
+
+        // Sniffing scans config itself, but not the README.
+        assert_eq!(names(Scanner::new().sniff_content(true)),
+                   vec!["config header", "detail.inl inline", "main.cpp source"]);
+    }
+
+    #[test]
+    fn filter_scanned_paths() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("modules");
+        let edge_count = |include: Vec<&str>, exclude: Vec<&str>| {
+            Scanner::new()
+                .path_filter(PathFilter::new(include, exclude).unwrap())
+                .scan_tree(&testdata_dir)
+                .graph
+                .graph
+                .edge_count()
+        };
+
+        assert_eq!(edge_count(vec![], vec![]), 7);
+
+        // util/strings.h is no longer scanned, but is still included.
+        assert_eq!(edge_count(vec![], vec!["util/"]), 6);
+        assert_eq!(edge_count(vec!["*.cpp"], vec![]), 4);
+        assert_eq!(edge_count(vec!["/*"], vec!["*.h"]), 4);
+    }
+
+    #[test]
+    fn skip_ignored_and_hidden_directories() {
+        let root_dir = env::temp_dir().join(format!("include2dot-test-walk-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root_dir);
+        for dir in &["build", ".cache", "src"] {
+            fs::create_dir_all(root_dir.join(dir)).unwrap();
+        }
+        for &(file, text) in &[(".gitignore", "build/\n"),
+                               ("src/main.cpp", "#include \"a.h\"\n"),
+                               ("src/a.h", "#include \"b.h\"\n"),
+                               ("build/a.h", "#include \"generated.h\"\n"),
+                               (".cache/a.h", "#include \"cached.h\"\n")] {
+            fs::write(root_dir.join(file), text).unwrap();
+        }
+        // A link back to the root would be walked forever if it were followed
+        // blindly.
+        #[cfg(unix)]
+        ::std::os::unix::fs::symlink(&root_dir, root_dir.join("src").join("root")).unwrap();
+
+        let includes = |scanner: Scanner| {
+            let graph = scanner.scan_tree(&root_dir).graph;
+            let mut names = graph.graph
+                .raw_edges()
+                .iter()
+                .map(|edge| graph.graph[edge.target()].path.file_name().unwrap().to_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        assert_eq!(includes(Scanner::new()), vec!["a.h", "b.h", "generated.h"]);
+        assert_eq!(includes(Scanner::new().respect_ignore_files(true)), vec!["a.h", "b.h"]);
+        assert_eq!(includes(Scanner::new().scan_hidden(true)),
+                   vec!["a.h", "b.h", "cached.h", "generated.h"]);
+        assert_eq!(includes(Scanner::new().respect_ignore_files(true).follow_links(true)),
+                   vec!["a.h", "b.h"]);
+
+        fs::remove_dir_all(&root_dir).unwrap();
+    }
+
+    #[test]
+    fn parallel_scan_is_deterministic() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("complex");
+
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
+
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"]);
+
+        // The nodes and edges are added in the same order, however many threads scan.
+        let scan_with = |jobs: usize| {
+            let graph = scanner.clone().jobs(jobs).scan_tree(&testdata_dir).graph;
+            let nodes = graph.graph.node_indices().map(|idx| graph.graph[idx].clone()).collect::<Vec<_>>();
+            let edges = graph.graph.raw_edges()
+                .iter()
+                .map(|edge| (edge.source().index(), edge.target().index(), edge.weight.line))
+                .collect::<Vec<_>>();
+            (nodes, edges)
+        };
+
+        let single = scan_with(1);
+        assert_eq!(single.0.len(), 12);
+
+        // Nodes are sorted by path.
+        let mut sorted_nodes = single.0.clone();
+        sorted_nodes.sort();
+        assert_eq!(single.0, sorted_nodes);
+
+        assert_eq!(scan_with(4), single);
+        assert_eq!(scan_with(0), single);
+    }
+
+    #[test]
+    fn cached_scan_matches_full_scan() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("complex");
+        let cache_file = env::temp_dir().join(format!("include2dot-test-cache-{}.json", std::process::id()));
+        let _ = fs::remove_file(&cache_file);
+
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
+
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"]);
+        let cached_scanner = scanner.clone().cache_file(cache_file.clone());
+
+        let full = scanner.scan_tree(&testdata_dir).graph;
+        let first = cached_scanner.scan_tree(&testdata_dir).graph;
+        let cache = ScanCache::load(&cache_file, &MacroMap::new()).unwrap();
+        let second = cached_scanner.scan_tree(&testdata_dir).graph;
+
+        // Scanning another tree adds to the cache, rather than replacing it.
+        cached_scanner.scan_tree(&testdata_dir.with_file_name("cycles"));
+        let merged = ScanCache::load(&cache_file, &MacroMap::new()).unwrap();
+
+        // A damaged cache is reported and replaced.
+        fs::write(&cache_file, "not json").unwrap();
+        let rescan = cached_scanner.scan_tree(&testdata_dir);
+        assert_eq!(rescan.errors.len(), 1);
+        assert!(rescan.errors[0].message.starts_with("Ignoring scan cache"));
+        assert_eq!(ScanCache::load(&cache_file, &MacroMap::new()).unwrap().len(), 6);
+        fs::remove_file(&cache_file).unwrap();
+
+        // Every scanned file is cached, and reusing them gives the same graph.
+        assert_eq!(cache.len(), 6);
+        assert_eq!(merged.len(), 6 + 7);
+        for graph in &[first, second] {
+            assert_eq!(graph.graph.node_count(), full.graph.node_count());
+            assert_eq!(graph.graph.edge_count(), full.graph.edge_count());
+        }
+    }
+
     #[test]
     fn filter_included_by_subgraph() {
         let testdata_dir = env::current_dir().unwrap().join("testdata").join("complex");
 
-        let mut search_paths = Vec::new();
-        search_paths.push(PathBuf::from(&testdata_dir));
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
 
-        let mut extensions = HashSet::new();
-        extensions.insert(OsString::from("h"));
-        extensions.insert(OsString::from("cpp"));
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"]);
 
-        let graph =
-            find_includes_in_tree(&testdata_dir, &search_paths, &extensions, true, true, &None);
+        let graph = scanner.scan_tree(&testdata_dir).graph;
 
         let idx_list = graph.find(|n| n.path.file_name().unwrap() == "test_1.cpp");
         assert_eq!(idx_list.len(), 1);
@@ -230,15 +944,14 @@ mod test {
     fn filter_that_includes_subgraph() {
         let testdata_dir = env::current_dir().unwrap().join("testdata").join("complex");
 
-        let mut search_paths = Vec::new();
-        search_paths.push(PathBuf::from(&testdata_dir));
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
 
-        let mut extensions = HashSet::new();
-        extensions.insert(OsString::from("h"));
-        extensions.insert(OsString::from("cpp"));
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"]);
 
-        let graph =
-            find_includes_in_tree(&testdata_dir, &search_paths, &extensions, true, true, &None);
+        let graph = scanner.scan_tree(&testdata_dir).graph;
 
         let idx_list = graph.find(|n| n.path.file_name().unwrap() == "inc_1.h");
         assert_eq!(idx_list.len(), 1);
@@ -260,15 +973,14 @@ mod test {
     fn filter_related_to_subgraph() {
         let testdata_dir = env::current_dir().unwrap().join("testdata").join("complex");
 
-        let mut search_paths = Vec::new();
-        search_paths.push(PathBuf::from(&testdata_dir));
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
 
-        let mut extensions = HashSet::new();
-        extensions.insert(OsString::from("h"));
-        extensions.insert(OsString::from("cpp"));
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"]);
 
-        let graph =
-            find_includes_in_tree(&testdata_dir, &search_paths, &extensions, true, true, &None);
+        let graph = scanner.scan_tree(&testdata_dir).graph;
 
         let idx_list = graph.find(|n| n.path.file_name().unwrap() == "inc_1.h");
         assert_eq!(idx_list.len(), 1);
@@ -286,4 +998,305 @@ mod test {
         assert!(graph.find(|n| n.path.file_name().unwrap() == "a.cpp").len() == 1);
         assert!(graph.find(|n| n.path.file_name().unwrap() == "vector").len() == 1);
     }
+
+    #[test]
+    fn report_unresolved_includes() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("simple");
+
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from("/nonexistent"));
+
+        let scan = Scanner::new().search_paths(search_paths).scan_tree(&testdata_dir);
+
+        // Nothing in a.cpp can be found.
+        assert_eq!(scan.unresolved.len(), 4);
+        assert!(scan.graph.nodes().filter(|n| !n.is_resolved()).count() == 4);
+
+        let quoted = &scan.unresolved[0];
+        assert_eq!(quoted.file, testdata_dir.join("a.cpp"));
+        assert_eq!((quoted.line, quoted.column), (1, 1));
+        assert_eq!(quoted.spelling, "\"inc_1.h\"");
+        assert_eq!(quoted.searched, vec![testdata_dir.clone(), PathBuf::from("/nonexistent")]);
+
+        // Angle includes don't search the including file's directory.
+        let angle = &scan.unresolved[3];
+        assert_eq!(angle.spelling, "<test_harness_2.h>");
+        assert_eq!(angle.searched, vec![PathBuf::from("/nonexistent")]);
+
+        // A missing "config.h" in one directory is a different file than one
+        // missing from another, but <missing.h> is the same everywhere.
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("unresolved");
+        let graph = Scanner::new().scan_tree(&testdata_dir).graph;
+        assert_eq!(graph.find(|n| n.path == Path::new("config.h")).len(), 2);
+        assert_eq!(graph.find(|n| n.path == Path::new("missing.h")).len(), 1);
+    }
+
+    #[test]
+    fn merge_modules() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("modules");
+
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
+
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"]);
+
+        let graph = scanner.scan_tree(&testdata_dir).graph;
+        assert_eq!(graph.graph.node_count(), 6);
+
+        let merged = graph.merge_nodes(FileNode::to_module);
+        assert_eq!(merged.graph.node_count(), 4);
+        assert_eq!(merged.graph.edge_count(), 5);
+
+        // foo.cpp and foo.h should be a single node.
+        assert!(merged.find(|n| n.path.file_name().unwrap() == "foo").len() == 1);
+        assert!(merged.find(|n| n.path.file_name().unwrap() == "foo.h").is_empty());
+    }
+
+    #[test]
+    fn merge_directories() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("modules");
+
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
+
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"]);
+
+        let graph = scanner.scan_tree(&testdata_dir).graph;
+
+        let merged = graph.merge_nodes(FileNode::to_directory);
+        assert_eq!(merged.graph.node_count(), 2);
+        assert_eq!(merged.graph.edge_count(), 1);
+
+        assert!(merged.find(|n| n.path.file_name().unwrap() == "modules").len() == 1);
+        assert!(merged.find(|n| n.path.file_name().unwrap() == "util").len() == 1);
+    }
+
+    #[test]
+    fn parse_conditional_includes() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("conditional");
+
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
+
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"]);
+
+        let has_node = |graph: &HashGraph<FileNode>, name: &str| {
+            graph.find(|n| n.path.file_name().unwrap() == name).len() == 1
+        };
+
+        // With no macros defined, only the #else branch is live.
+        let graph = scanner.scan_tree(&testdata_dir).graph;
+        assert_eq!(graph.graph.node_count(), 4);
+        assert!(has_node(&graph, "extra.h"));
+        assert!(has_node(&graph, "fallback.h"));
+
+        let mut defines = MacroMap::new();
+        defines.insert(String::from("__linux__"), String::from("1"));
+        defines.insert(String::from("VERSION"), String::from("2"));
+        let graph = scanner.clone().defines(defines.clone()).scan_tree(&testdata_dir).graph;
+        assert_eq!(graph.graph.node_count(), 4);
+        assert!(has_node(&graph, "linux_only.h"));
+        assert!(!has_node(&graph, "fallback.h"));
+
+        // Keeping conditional includes adds them as inactive edges.
+        let graph = scanner.defines(defines).keep_conditional(true).scan_tree(&testdata_dir).graph;
+        assert_eq!(graph.graph.node_count(), 7);
+        assert_eq!(graph.graph.raw_edges().iter().filter(|edge| !edge.weight.is_active).count(), 3);
+    }
+
+    #[test]
+    fn scan_compile_commands() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("compdb");
+
+        let json = format!(r#"[
+            {{
+                "directory": {0:?},
+                "arguments": ["c++", "-Iinclude", "-iquote", "config", "-DUSE_EXTRA",
+                              "-c", "src/main.cpp"],
+                "file": "src/main.cpp"
+            }},
+            {{
+                "directory": {0:?},
+                "command": "c++ -I include -c src/other.cpp",
+                "file": "src/other.cpp"
+            }},
+            {{
+                "directory": {0:?},
+                "command": "c++ -c src/deleted.cpp",
+                "file": "src/deleted.cpp"
+            }}
+        ]"#,
+                           testdata_dir.to_str().unwrap());
+        let commands = compile_commands::parse_compile_commands(&json).unwrap();
+
+        let scan = Scanner::new().scan_compile_commands(&commands);
+        let graph = scan.graph;
+
+        // Units that no longer exist are reported, and the others still scanned.
+        assert_eq!(scan.errors.len(), 1);
+        assert!(scan.errors[0].path.as_ref().unwrap().ends_with("deleted.cpp"));
+
+        // unused.cpp is not compiled, so it is not scanned.
+        assert_eq!(graph.graph.node_count(), 6);
+        assert!(graph.find(|n| n.path.file_name().unwrap() == "unused.cpp").is_empty());
+        assert!(graph.find(|n| n.path.file_name().unwrap() == "config.h").len() == 1);
+        assert!(graph.find(|n| n.path.file_name().unwrap() == "extra.h").len() == 1);
+        assert!(graph.find(|n| n.path.file_name().unwrap() == "detail.h").len() == 1);
+
+        // Every include should have been resolved.
+        assert!(graph.find(|n| n.path.is_relative()).is_empty());
+    }
+
+    #[test]
+    fn find_include_cycles() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("cycles");
+
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
+
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"]);
+
+        let mut graph = scanner.scan_tree(&testdata_dir).graph;
+
+        let cycle_names = |graph: &HashGraph<FileNode>| {
+            let mut cycles = graph.find_cycles()
+                .iter()
+                .map(|cycle| {
+                    let mut names = cycle.iter()
+                        .map(|idx| graph.graph[*idx].path.file_name().unwrap().to_str().unwrap())
+                        .collect::<Vec<_>>();
+                    names.sort();
+                    names.join(" ")
+                })
+                .collect::<Vec<_>>();
+            cycles.sort();
+            cycles
+        };
+
+        // a.h <-> b.h, c.h -> d.h -> e.h -> c.h, and self.h including itself.
+        assert_eq!(cycle_names(&graph), vec!["a.h b.h", "c.h d.h e.h", "self.h"]);
+
+        // Every edge except the ones from main.cpp is on a cycle.
+        assert_eq!(graph.cycle_edges().len(), 6);
+
+        // An include in an inactive #if block doesn't close a cycle.
+        let a_idx = graph.find(|node| node.path.ends_with("a.h"))[0];
+        let b_idx = graph.find(|node| node.path.ends_with("b.h"))[0];
+        let edge_idx = graph.graph.find_edge(a_idx, b_idx).unwrap();
+        graph.graph[edge_idx].is_active = false;
+        assert_eq!(cycle_names(&graph), vec!["c.h d.h e.h", "self.h"]);
+        assert_eq!(graph.cycle_edges().len(), 4);
+    }
+
+    #[test]
+    fn query_direct_includes() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("cost");
+
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
+
+        let graph = Scanner::new().search_paths(search_paths).scan_tree(&testdata_dir).graph;
+
+        let node = |name: &str| FileNode::from_path(&testdata_dir.join(name), false);
+        let names = |nodes: Vec<&FileNode>| {
+            let mut names = nodes.iter()
+                .map(|n| n.path.file_name().unwrap().to_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+
+        assert_eq!(names(graph.includes_of(&node("a.cpp"))), vec!["big.h", "common.h"]);
+        assert_eq!(names(graph.includers_of(&node("common.h"))), vec!["a.cpp", "b.cpp"]);
+        assert!(graph.includes_of(&node("missing.h")).is_empty());
+        assert_eq!(graph.nodes().count(), 6);
+
+        // Edges record where and how the file was included.
+        let a_idx = graph.node_index(&node("a.cpp")).unwrap();
+        let big_idx = graph.node_index(&node("big.h")).unwrap();
+        let edge = &graph.graph[graph.graph.find_edge(a_idx, big_idx).unwrap()];
+        assert_eq!((edge.line, edge.spelling.as_str()), (2, "\"big.h\""));
+    }
+
+    #[test]
+    fn include_costs() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("cost");
+
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
+
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"]);
+
+        let mut graph = scanner.scan_tree(&testdata_dir).graph;
+
+        // A header that nothing includes isn't compiled on its own.
+        graph.add_edge(FileNode::from_path(&testdata_dir.join("unused.h"), false),
+                       FileNode::from_path(&testdata_dir.join("common.h"), false),
+                       IncludeEdge {
+                           is_active: true,
+                           line: 1,
+                           spelling: String::from("\"common.h\""),
+                       });
+
+        // Count every file as 10 bytes.
+        let costs = graph.include_costs(|_| 10, |node| node.kind() == file_node::FileKind::Source);
+        let cost_of = |name: &str| {
+            let idx = graph.find(|n| n.path.file_name().unwrap() == name)[0];
+            let cost = &costs[idx.index()];
+            (cost.reach, cost.reach_bytes, cost.includers)
+        };
+
+        assert_eq!(cost_of("a.cpp"), (4, 40, 0));
+        assert_eq!(cost_of("b.cpp"), (2, 20, 0));
+        assert_eq!(cost_of("common.h"), (1, 10, 2));
+        assert_eq!(cost_of("base.h"), (0, 0, 2));
+        assert_eq!(cost_of("unused.h"), (2, 20, 0));
+
+        // big.h and loop.h include each other, but don't count themselves.
+        assert_eq!(cost_of("big.h"), (2, 20, 1));
+        assert_eq!(cost_of("loop.h"), (2, 20, 1));
+    }
+
+    #[test]
+    fn transitive_reduction() {
+        let testdata_dir = env::current_dir().unwrap().join("testdata").join("reduce");
+
+        let mut search_paths = SearchPaths::default();
+        search_paths.angle.push(PathBuf::from(&testdata_dir));
+
+        let scanner = Scanner::new()
+            .search_paths(search_paths)
+            .extensions(vec!["h", "cpp"]);
+
+        let graph = scanner.scan_tree(&testdata_dir).graph;
+        assert_eq!(graph.graph.edge_count(), 9);
+
+        let name_of = |idx: NodeIndex| graph.graph[idx].path.file_name().unwrap().to_str().unwrap();
+        let mut redundant = graph.redundant_edges()
+            .iter()
+            .map(|edge_idx| {
+                let (src_idx, dst_idx) = graph.graph.edge_endpoints(*edge_idx).unwrap();
+                format!("{} {}", name_of(src_idx), name_of(dst_idx))
+            })
+            .collect::<Vec<_>>();
+        redundant.sort();
+
+        // a.cpp includes b.h twice. d.h and e.h include each other, so both
+        // c.h -> d.h and c.h -> e.h are kept.
+        assert_eq!(redundant, vec!["a.cpp b.h", "a.cpp c.h", "a.cpp d.h"]);
+
+        let reduced = graph.transitive_reduction();
+        assert_eq!(reduced.graph.node_count(), graph.graph.node_count());
+        assert_eq!(reduced.graph.edge_count(), 6);
+    }
 }
@@ -0,0 +1,139 @@
+
+use std::io::{self, Write};
+
+use petgraph::visit::EdgeRef;
+
+use file_node::FileNode;
+use hash_graph::HashGraph;
+use writer::{escape_xml, GraphWriter, NodeAttributes};
+
+// Writes graphs as GEXF 1.2, Gephi's native format. Nodes carry their path,
+// directory, extension, system flag and fan-in/fan-out counts.
+#[derive(Debug, Clone, Default)]
+pub struct GexfWriter;
+
+impl GexfWriter {
+    pub fn new() -> GexfWriter {
+        GexfWriter
+    }
+}
+
+impl GraphWriter for GexfWriter {
+    fn write_graph(&self, out: &mut dyn Write, graph: &HashGraph<FileNode>) -> Result<(), io::Error> {
+        writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(out, "<gexf xmlns=\"http://www.gexf.net/1.2draft\" version=\"1.2\">")?;
+        writeln!(out, "  <graph mode=\"static\" defaultedgetype=\"directed\">")?;
+
+        // Attribute declarations. Values refer to them by their position.
+        let node_attributes = [("path", "string"),
+                               ("directory", "string"),
+                               ("extension", "string"),
+                               ("system", "boolean"),
+                               ("fan_in", "integer"),
+                               ("fan_out", "integer"),
+                               ("kind", "string")];
+        let edge_attributes = [("line", "integer"), ("include", "string"), ("active", "boolean")];
+        write_attribute_declarations(out, "node", &node_attributes)?;
+        write_attribute_declarations(out, "edge", &edge_attributes)?;
+
+        // Nodes
+        // Format:
+        //     <node id="0" label="main.cpp">
+        //       <attvalues>
+        //         <attvalue for="0" value="/src/main.cpp"/>
+        //       </attvalues>
+        //     </node>
+        writeln!(out, "    <nodes>")?;
+        for node_idx in graph.graph.node_indices() {
+            let attributes = NodeAttributes::new(graph, node_idx);
+            writeln!(out,
+                     "      <node id=\"{}\" label=\"{}\">",
+                     node_idx.index(),
+                     escape_xml(&attributes.label))?;
+            write_attribute_values(out,
+                                   &[attributes.path,
+                                     attributes.directory,
+                                     attributes.extension,
+                                     attributes.is_system.to_string(),
+                                     attributes.fan_in.to_string(),
+                                     attributes.fan_out.to_string(),
+                                     attributes.kind.to_string()])?;
+            writeln!(out, "      </node>")?;
+        }
+        writeln!(out, "    </nodes>")?;
+
+        // Edges
+        writeln!(out, "    <edges>")?;
+        for edge in graph.graph.edge_references() {
+            let include = edge.weight();
+            writeln!(out,
+                     "      <edge id=\"{}\" source=\"{}\" target=\"{}\">",
+                     edge.id().index(),
+                     edge.source().index(),
+                     edge.target().index())?;
+            write_attribute_values(out,
+                                   &[include.line.to_string(),
+                                     include.spelling.clone(),
+                                     include.is_active.to_string()])?;
+            writeln!(out, "      </edge>")?;
+        }
+        writeln!(out, "    </edges>")?;
+
+        writeln!(out, "  </graph>")?;
+        writeln!(out, "</gexf>")
+    }
+}
+
+fn write_attribute_declarations(out: &mut dyn Write,
+                                class: &str,
+                                attributes: &[(&str, &str)])
+                                -> Result<(), io::Error> {
+    writeln!(out, "    <attributes class=\"{}\">", class)?;
+    for (id, &(title, attr_type)) in attributes.iter().enumerate() {
+        writeln!(out,
+                 "      <attribute id=\"{}\" title=\"{}\" type=\"{}\"/>",
+                 id,
+                 title,
+                 attr_type)?;
+    }
+    writeln!(out, "    </attributes>")
+}
+
+fn write_attribute_values(out: &mut dyn Write, values: &[String]) -> Result<(), io::Error> {
+    writeln!(out, "        <attvalues>")?;
+    for (id, value) in values.iter().enumerate() {
+        writeln!(out,
+                 "          <attvalue for=\"{}\" value=\"{}\"/>",
+                 id,
+                 escape_xml(value))?;
+    }
+    writeln!(out, "        </attvalues>")
+}
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_write_gexf() {
+    use hash_graph::IncludeEdge;
+
+    let mut graph = HashGraph::new();
+    graph.add_edge(FileNode::new("/src/main.cpp", false),
+                   FileNode::new("/src/a&b.h", false),
+                   IncludeEdge {
+                       is_active: false,
+                       line: 7,
+                       spelling: String::from("\"a&b.h\""),
+                   });
+
+    let mut out = Vec::new();
+    GexfWriter::new().write_graph(&mut out, &graph).unwrap();
+    let text = String::from_utf8(out).unwrap();
+
+    assert!(text.contains("      <attribute id=\"4\" title=\"fan_in\" type=\"integer\"/>\n"));
+    assert!(text.contains("      <node id=\"1\" label=\"a&amp;b.h\">\n"));
+    assert!(text.contains("          <attvalue for=\"4\" value=\"1\"/>\n"));
+    assert!(text.contains("          <attvalue for=\"6\" value=\"header\"/>\n"));
+    assert!(text.contains("      <edge id=\"0\" source=\"0\" target=\"1\">\n"));
+    assert!(text.contains("          <attvalue for=\"1\" value=\"&quot;a&amp;b.h&quot;\"/>\n"));
+    assert!(text.ends_with("  </graph>\n</gexf>\n"));
+}
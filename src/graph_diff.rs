@@ -0,0 +1,202 @@
+
+use std::collections::{HashMap, HashSet};
+
+use petgraph::prelude::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+
+use file_node::FileNode;
+use hash_graph::HashGraph;
+
+// ----------------------------------------------------------------------------
+
+// A file whose transitive include count differs between the two graphs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReachChange {
+    pub node: NodeIndex, // Index in the combined graph.
+    pub old_reach: usize,
+    pub new_reach: usize,
+}
+
+impl ReachChange {
+    // The change as a percentage of the old reach. A file that used to
+    // include nothing counts as having a reach of one.
+    pub fn percent(&self) -> usize {
+        let difference = (self.new_reach as isize - self.old_reach as isize).unsigned_abs();
+        difference * 100 / self.old_reach.max(1)
+    }
+}
+
+// The differences between two include graphs, e.g. before and after a change.
+// Files are matched by path, and includes by the files at either end, so a
+// moved #include line isn't reported.
+//
+// The combined graph holds the files and includes of both graphs, and all of
+// the indices refer to it. This makes it easy to draw the changes, e.g.
+//
+//     let diff = GraphDiff::new(&old_graph, &new_graph);
+//     let mut writer = DotWriter::new();
+//     for edge_idx in &diff.added_edges {
+//         writer = writer.edge_color(*edge_idx, "green");
+//     }
+#[derive(Debug)]
+pub struct GraphDiff {
+    pub graph: HashGraph<FileNode>, // Combined graph, sorted by path.
+    pub added_files: Vec<NodeIndex>,
+    pub removed_files: Vec<NodeIndex>,
+    pub added_edges: Vec<EdgeIndex>,
+    pub removed_edges: Vec<EdgeIndex>,
+    pub reach_changes: Vec<ReachChange>, // Files in both graphs, largest change first.
+}
+
+impl GraphDiff {
+    pub fn new(old: &HashGraph<FileNode>, new: &HashGraph<FileNode>) -> GraphDiff {
+        let old_edges = edge_set(old);
+        let new_edges = edge_set(new);
+
+        // Add every file and include of both graphs. Includes made more than
+        // once only get a single edge.
+        let mut combined = HashGraph::new();
+        for node in old.nodes().chain(new.nodes()) {
+            combined.add_node(node.clone());
+        }
+        for graph in &[old, new] {
+            for edge in graph.graph.edge_references() {
+                let src_idx = combined.add_node(graph.graph[edge.source()].clone());
+                let dst_idx = combined.add_node(graph.graph[edge.target()].clone());
+                if combined.graph.find_edge(src_idx, dst_idx).is_none() {
+                    combined.graph.add_edge(src_idx, dst_idx, edge.weight().clone());
+                }
+            }
+        }
+        let combined = combined.sorted();
+
+        let added_files = combined.find(|node| !old.contains_node(node));
+        let removed_files = combined.find(|node| !new.contains_node(node));
+
+        let mut added_edges = Vec::new();
+        let mut removed_edges = Vec::new();
+        for edge in combined.graph.edge_references() {
+            let key = (&combined.graph[edge.source()], &combined.graph[edge.target()]);
+            match (old_edges.contains(&key), new_edges.contains(&key)) {
+                (false, true) => added_edges.push(edge.id()),
+                (true, false) => removed_edges.push(edge.id()),
+                _ => {}
+            }
+        }
+
+        // Compare the transitive include counts of files in both graphs.
+        let old_reach = reach_by_node(old);
+        let new_reach = reach_by_node(new);
+        let mut reach_changes = combined.graph
+            .node_indices()
+            .filter_map(|node_idx| {
+                let node = &combined.graph[node_idx];
+                match (old_reach.get(node), new_reach.get(node)) {
+                    (Some(&old_reach), Some(&new_reach)) if old_reach != new_reach => {
+                        Some(ReachChange {
+                            node: node_idx,
+                            old_reach,
+                            new_reach,
+                        })
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        reach_changes.sort_by(|a, b| {
+            let difference = |change: &ReachChange| (change.new_reach as isize - change.old_reach as isize).abs();
+            difference(b).cmp(&difference(a)).then_with(|| a.node.cmp(&b.node))
+        });
+
+        GraphDiff {
+            graph: combined,
+            added_files,
+            removed_files,
+            added_edges,
+            removed_edges,
+            reach_changes,
+        }
+    }
+
+    // True if no files or includes were added or removed.
+    pub fn is_empty(&self) -> bool {
+        self.added_files.is_empty() && self.removed_files.is_empty() && self.added_edges.is_empty() &&
+        self.removed_edges.is_empty()
+    }
+
+    // The reach changes of at least [percent] percent, largest first.
+    pub fn significant_reach_changes(&self, percent: usize) -> Vec<&ReachChange> {
+        self.reach_changes.iter().filter(|change| change.percent() >= percent).collect()
+    }
+}
+
+// The includes of a graph, as pairs of files.
+fn edge_set(graph: &HashGraph<FileNode>) -> HashSet<(&FileNode, &FileNode)> {
+    graph.graph
+        .edge_references()
+        .map(|edge| (&graph.graph[edge.source()], &graph.graph[edge.target()]))
+        .collect()
+}
+
+// The number of files each file transitively includes.
+fn reach_by_node(graph: &HashGraph<FileNode>) -> HashMap<&FileNode, usize> {
+    graph.include_costs(|_| 0, |_| false)
+        .into_iter()
+        .map(|cost| (&graph.graph[cost.node], cost.reach))
+        .collect()
+}
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_graph_diff() {
+    use hash_graph::IncludeEdge;
+
+    let edge = IncludeEdge {
+        is_active: true,
+        line: 1,
+        spelling: String::new(),
+    };
+    let file = |name: &str| FileNode::new(name, false);
+
+    // main.cpp stops including old.h, and starts including new.h, which
+    // pulls in two more headers.
+    let mut old = HashGraph::new();
+    old.add_edge(file("main.cpp"), file("a.h"), edge.clone());
+    old.add_edge(file("main.cpp"), file("old.h"), edge.clone());
+
+    let mut new = HashGraph::new();
+    new.add_edge(file("main.cpp"), file("a.h"), edge.clone());
+    new.add_edge(file("main.cpp"), file("new.h"), edge.clone());
+    new.add_edge(file("new.h"), file("b.h"), edge.clone());
+    new.add_edge(file("new.h"), file("c.h"), edge);
+
+    let diff = GraphDiff::new(&old, &new);
+    let name_of = |idx: NodeIndex| diff.graph.graph[idx].path.to_str().unwrap();
+    let edge_names = |edges: &[EdgeIndex]| {
+        edges.iter()
+            .map(|edge_idx| {
+                let (src_idx, dst_idx) = diff.graph.graph.edge_endpoints(*edge_idx).unwrap();
+                format!("{} {}", name_of(src_idx), name_of(dst_idx))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert!(!diff.is_empty());
+    assert_eq!(diff.graph.graph.node_count(), 6);
+    assert_eq!(diff.added_files.iter().map(|idx| name_of(*idx)).collect::<Vec<_>>(),
+               vec!["b.h", "c.h", "new.h"]);
+    assert_eq!(diff.removed_files.iter().map(|idx| name_of(*idx)).collect::<Vec<_>>(),
+               vec!["old.h"]);
+    assert_eq!(edge_names(&diff.added_edges), vec!["main.cpp new.h", "new.h b.h", "new.h c.h"]);
+    assert_eq!(edge_names(&diff.removed_edges), vec!["main.cpp old.h"]);
+
+    // main.cpp went from 2 included files to 4.
+    assert_eq!(diff.reach_changes.len(), 1);
+    assert_eq!(name_of(diff.reach_changes[0].node), "main.cpp");
+    assert_eq!((diff.reach_changes[0].old_reach, diff.reach_changes[0].new_reach), (2, 4));
+    assert_eq!(diff.reach_changes[0].percent(), 100);
+    assert!(diff.significant_reach_changes(150).is_empty());
+
+    assert!(GraphDiff::new(&new, &new).is_empty());
+}
@@ -0,0 +1,117 @@
+
+use std::io::{self, Write};
+
+use petgraph::visit::EdgeRef;
+
+use file_node::FileNode;
+use hash_graph::HashGraph;
+use writer::{escape_xml, GraphWriter, NodeAttributes};
+
+// Writes graphs as GraphML, which yEd and Gephi can open. Nodes carry their
+// path, directory, extension, system flag and fan-in/fan-out counts.
+#[derive(Debug, Clone, Default)]
+pub struct GraphMlWriter;
+
+impl GraphMlWriter {
+    pub fn new() -> GraphMlWriter {
+        GraphMlWriter
+    }
+}
+
+impl GraphWriter for GraphMlWriter {
+    fn write_graph(&self, out: &mut dyn Write, graph: &HashGraph<FileNode>) -> Result<(), io::Error> {
+        writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+        writeln!(out, "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">")?;
+
+        // Attribute declarations
+        let keys = [("label", "node", "string"),
+                    ("path", "node", "string"),
+                    ("directory", "node", "string"),
+                    ("extension", "node", "string"),
+                    ("kind", "node", "string"),
+                    ("system", "node", "boolean"),
+                    ("fan_in", "node", "int"),
+                    ("fan_out", "node", "int"),
+                    ("line", "edge", "int"),
+                    ("include", "edge", "string"),
+                    ("active", "edge", "boolean")];
+        for &(name, domain, attr_type) in &keys {
+            writeln!(out,
+                     "  <key id=\"{0}\" for=\"{1}\" attr.name=\"{0}\" attr.type=\"{2}\"/>",
+                     name,
+                     domain,
+                     attr_type)?;
+        }
+
+        writeln!(out, "  <graph id=\"includes\" edgedefault=\"directed\">")?;
+
+        // Nodes
+        // Format:
+        //     <node id="n0">
+        //       <data key="label">main.cpp</data>
+        //     </node>
+        for node_idx in graph.graph.node_indices() {
+            let attributes = NodeAttributes::new(graph, node_idx);
+            writeln!(out, "    <node id=\"n{}\">", node_idx.index())?;
+            write_data(out, "label", &attributes.label)?;
+            write_data(out, "path", &attributes.path)?;
+            write_data(out, "directory", &attributes.directory)?;
+            write_data(out, "extension", &attributes.extension)?;
+            write_data(out, "kind", attributes.kind.name())?;
+            write_data(out, "system", &attributes.is_system.to_string())?;
+            write_data(out, "fan_in", &attributes.fan_in.to_string())?;
+            write_data(out, "fan_out", &attributes.fan_out.to_string())?;
+            writeln!(out, "    </node>")?;
+        }
+
+        // Edges
+        for edge in graph.graph.edge_references() {
+            let include = edge.weight();
+            writeln!(out,
+                     "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\">",
+                     edge.id().index(),
+                     edge.source().index(),
+                     edge.target().index())?;
+            write_data(out, "line", &include.line.to_string())?;
+            write_data(out, "include", &include.spelling)?;
+            write_data(out, "active", &include.is_active.to_string())?;
+            writeln!(out, "    </edge>")?;
+        }
+
+        writeln!(out, "  </graph>")?;
+        writeln!(out, "</graphml>")
+    }
+}
+
+fn write_data(out: &mut dyn Write, key: &str, value: &str) -> Result<(), io::Error> {
+    writeln!(out, "      <data key=\"{}\">{}</data>", key, escape_xml(value))
+}
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_write_graphml() {
+    use hash_graph::IncludeEdge;
+
+    let mut graph = HashGraph::new();
+    graph.add_edge(FileNode::new("/src/main.cpp", false),
+                   FileNode::new("vector", true),
+                   IncludeEdge {
+                       is_active: true,
+                       line: 3,
+                       spelling: String::from("<vector>"),
+                   });
+
+    let mut out = Vec::new();
+    GraphMlWriter::new().write_graph(&mut out, &graph).unwrap();
+    let text = String::from_utf8(out).unwrap();
+
+    assert!(text.contains("  <key id=\"fan_in\" for=\"node\" attr.name=\"fan_in\" attr.type=\"int\"/>\n"));
+    assert!(text.contains("    <node id=\"n0\">\n      <data key=\"label\">main.cpp</data>\n"));
+    assert!(text.contains("      <data key=\"directory\">/src</data>\n"));
+    assert!(text.contains("      <data key=\"fan_out\">1</data>\n"));
+    assert!(text.contains("      <data key=\"kind\">source</data>\n"));
+    assert!(text.contains("    <edge id=\"e0\" source=\"n0\" target=\"n1\">\n"));
+    assert!(text.contains("      <data key=\"include\">&lt;vector&gt;</data>\n"));
+    assert!(text.ends_with("  </graph>\n</graphml>\n"));
+}
@@ -1,27 +1,62 @@
 
 use std::fmt::Display;
 use std::hash::Hash;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 extern crate petgraph;
 use petgraph::Graph;
-use petgraph::prelude::NodeIndex;
+use petgraph::prelude::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
 
+/// Transitive include cost of a single node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludeCost {
+    pub node: NodeIndex,
+    pub reach: usize, // Unique files transitively included by the node.
+    pub reach_bytes: u64, // Total size of those files.
+    pub includers: usize, // Translation units that transitively include the node.
+}
+
+impl IncludeCost {
+    /// Estimated compile-time cost: the amount of code pulled in, multiplied
+    /// by the number of times it is compiled.
+    pub fn cost(&self) -> u64 {
+        self.reach as u64 * self.includers as u64
+    }
+}
+
+/// An include directive, stored on the edge from the including file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludeEdge {
+    pub is_active: bool, // False if the include is inside an inactive #if block.
+    pub line: usize, // 1-based line of the directive in the including file.
+    pub spelling: String, // The include as written, e.g. "<vector>" or "\"foo.h\"".
+}
+
+/// A directed graph of includes, where each node value appears only once, so
+/// nodes can be looked up by value as well as by index.
 #[derive(Debug)]
 pub struct HashGraph<T: Eq + PartialEq + Hash + Clone> {
-    pub graph: Graph<T, bool>,
+    pub graph: Graph<T, IncludeEdge>,
     indices: HashMap<T, NodeIndex>,
 }
 
+impl<T: Eq + PartialEq + Hash + Clone + Display> Default for HashGraph<T> {
+    fn default() -> HashGraph<T> {
+        HashGraph::new()
+    }
+}
+
 impl<T: Eq + PartialEq + Hash + Clone + Display> HashGraph<T> {
+    /// An empty graph.
     pub fn new() -> HashGraph<T> {
         HashGraph::<T> {
-            graph: Graph::<T, bool>::new(),
+            graph: Graph::<T, IncludeEdge>::new(),
             indices: HashMap::<T, NodeIndex>::new(),
         }
     }
 
-    // Return a sub-graph of all files included by the target node.
+    /// Return a sub-graph of all files included by the target node.
     pub fn filter_included_by(&self, root_node: NodeIndex) -> HashGraph<T> {
 
         let mut subgraph = HashGraph::new();
@@ -31,10 +66,12 @@ impl<T: Eq + PartialEq + Hash + Clone + Display> HashGraph<T> {
         queue.push_back(root_node);
 
         while let Some(node_idx) = queue.pop_front() {
-            for neighbor_idx in self.graph.neighbors_directed(node_idx, petgraph::Outgoing) {
+            for edge in self.graph.edges_directed(node_idx, petgraph::Outgoing) {
+                let neighbor_idx = edge.target();
                 if !subgraph.contains_node(&self.graph[neighbor_idx]) {
                     subgraph.add_edge(self.graph[node_idx].clone(),
-                                      self.graph[neighbor_idx].clone());
+                                      self.graph[neighbor_idx].clone(),
+                                      edge.weight().clone());
                     queue.push_back(neighbor_idx);
                 }
             }
@@ -43,7 +80,7 @@ impl<T: Eq + PartialEq + Hash + Clone + Display> HashGraph<T> {
         subgraph
     }
 
-    // Return a sub-graph of all files that include the target node.
+    /// Return a sub-graph of all files that include the target node.
     pub fn filter_that_includes(&self, root_node: NodeIndex) -> HashGraph<T> {
         // Run a breadth first search with inverted edges.
 
@@ -54,10 +91,12 @@ impl<T: Eq + PartialEq + Hash + Clone + Display> HashGraph<T> {
         queue.push_back(root_node);
 
         while let Some(node_idx) = queue.pop_front() {
-            for neighbor_idx in self.graph.neighbors_directed(node_idx, petgraph::Incoming) {
+            for edge in self.graph.edges_directed(node_idx, petgraph::Incoming) {
+                let neighbor_idx = edge.source();
                 if !subgraph.contains_node(&self.graph[neighbor_idx]) {
                     subgraph.add_edge(self.graph[neighbor_idx].clone(),
-                                      self.graph[node_idx].clone());
+                                      self.graph[node_idx].clone(),
+                                      edge.weight().clone());
                     queue.push_back(neighbor_idx);
                 }
             }
@@ -66,9 +105,9 @@ impl<T: Eq + PartialEq + Hash + Clone + Display> HashGraph<T> {
         subgraph
     }
 
-    // Return a sub-graph of all files that are related to the target node,
-    // both included-by and that-include. This effectively creates an hourglass
-    // shape centered around the target node.
+    /// Return a sub-graph of all files that are related to the target node,
+    /// both included-by and that-include. This effectively creates an hourglass
+    /// shape centered around the target node.
     pub fn filter_bidirectional(&self, root_node: NodeIndex) -> HashGraph<T> {
         // Run a breadth first search with inverted edges.
 
@@ -80,10 +119,12 @@ impl<T: Eq + PartialEq + Hash + Clone + Display> HashGraph<T> {
             queue.push_back(root_node);
 
             while let Some(node_idx) = queue.pop_front() {
-                for neighbor_idx in self.graph.neighbors_directed(node_idx, petgraph::Outgoing) {
+                for edge in self.graph.edges_directed(node_idx, petgraph::Outgoing) {
+                    let neighbor_idx = edge.target();
                     if !subgraph.contains_node(&self.graph[neighbor_idx]) {
                         subgraph.add_edge(self.graph[node_idx].clone(),
-                                          self.graph[neighbor_idx].clone());
+                                          self.graph[neighbor_idx].clone(),
+                                          edge.weight().clone());
                         queue.push_back(neighbor_idx);
                     }
                 }
@@ -97,10 +138,12 @@ impl<T: Eq + PartialEq + Hash + Clone + Display> HashGraph<T> {
             queue.push_back(root_node);
 
             while let Some(node_idx) = queue.pop_front() {
-                for neighbor_idx in self.graph.neighbors_directed(node_idx, petgraph::Incoming) {
+                for edge in self.graph.edges_directed(node_idx, petgraph::Incoming) {
+                    let neighbor_idx = edge.source();
                     if !subgraph.contains_node(&self.graph[neighbor_idx]) {
                         subgraph.add_edge(self.graph[neighbor_idx].clone(),
-                                          self.graph[node_idx].clone());
+                                          self.graph[node_idx].clone(),
+                                          edge.weight().clone());
                         queue.push_back(neighbor_idx);
                     }
                 }
@@ -110,28 +153,397 @@ impl<T: Eq + PartialEq + Hash + Clone + Display> HashGraph<T> {
         subgraph
     }
 
-    // Return a list of all node indices satisfying a predicate.
+    /// Return a copy of the graph with nodes collapsed together by a key function.
+    /// Nodes that map to the same key become one node. Duplicate edges and
+    /// self-loops created by the merge are dropped.
+    pub fn merge_nodes<F>(&self, key: F) -> HashGraph<T>
+        where F: Fn(&T) -> T
+    {
+        let mut merged = HashGraph::new();
+
+        // Keep nodes without edges, so a fully merged module still shows up.
+        for node_idx in self.graph.node_indices() {
+            merged.require_node(key(&self.graph[node_idx]));
+        }
+
+        for edge in self.graph.edge_references() {
+            let src_idx = merged.require_node(key(&self.graph[edge.source()]));
+            let dst_idx = merged.require_node(key(&self.graph[edge.target()]));
+            if src_idx == dst_idx {
+                continue;
+            }
+            // A merged edge is active if any of the original edges were. It
+            // keeps the line and spelling of the first one.
+            match merged.graph.find_edge(src_idx, dst_idx) {
+                Some(edge_idx) => merged.graph[edge_idx].is_active |= edge.weight().is_active,
+                None => {
+                    merged.graph.add_edge(src_idx, dst_idx, edge.weight().clone());
+                }
+            }
+        }
+
+        merged
+    }
+
+    /// Return a copy of the graph with the nodes in sorted order, and each
+    /// node's edges sorted by target and line. Indices in the copy only depend
+    /// on the nodes and edges, not the order they were added in.
+    pub fn sorted(&self) -> HashGraph<T>
+        where T: Ord
+    {
+        let mut sorted = HashGraph::new();
+
+        let mut nodes = self.graph.node_indices().collect::<Vec<_>>();
+        nodes.sort_by(|a, b| self.graph[*a].cmp(&self.graph[*b]));
+        for node_idx in nodes {
+            sorted.require_node(self.graph[node_idx].clone());
+        }
+
+        let mut edges = self.graph
+            .edge_references()
+            .map(|edge| {
+                (sorted.indices[&self.graph[edge.source()]],
+                 sorted.indices[&self.graph[edge.target()]],
+                 edge.weight())
+            })
+            .collect::<Vec<_>>();
+        edges.sort_by(|a, b| {
+            (a.0, a.1, a.2.line, &a.2.spelling, a.2.is_active)
+                .cmp(&(b.0, b.1, b.2.line, &b.2.spelling, b.2.is_active))
+        });
+        for (src_idx, dst_idx, edge) in edges {
+            sorted.graph.add_edge(src_idx, dst_idx, edge.clone());
+        }
+
+        sorted
+    }
+
+    /// Return the include cycles in the graph. Each cycle is a list of nodes
+    /// where every node includes the next, and the last includes the first.
+    /// Every node that is part of a cycle appears in at least one of them.
+    /// Inactive edges don't form cycles.
+    pub fn find_cycles(&self) -> Vec<Vec<NodeIndex>> {
+        let mut cycles = Vec::new();
+
+        // Nodes are only on a cycle with other nodes from the same strongly
+        // connected component. Find a short cycle through each node that isn't
+        // covered yet, so large components are broken down into readable chains.
+        let active_graph = self.active_graph();
+        for component in petgraph::algo::tarjan_scc(&active_graph) {
+            let members = component.iter().cloned().collect::<HashSet<_>>();
+            let mut covered = HashSet::new();
+
+            for &start_idx in &component {
+                if covered.contains(&start_idx) {
+                    continue;
+                }
+                if let Some(cycle) = self.shortest_cycle(start_idx, &members) {
+                    covered.extend(cycle.iter().cloned());
+                    cycles.push(cycle);
+                }
+            }
+        }
+
+        cycles
+    }
+
+    /// Return all active edges that are part of an include cycle.
+    pub fn cycle_edges(&self) -> Vec<EdgeIndex> {
+        let mut component_ids = HashMap::new();
+        for (component_id, component) in petgraph::algo::tarjan_scc(&self.active_graph()).iter().enumerate() {
+            for node_idx in component {
+                component_ids.insert(*node_idx, component_id);
+            }
+        }
+
+        // An edge is on a cycle if both ends are in the same component.
+        self.graph
+            .edge_references()
+            .filter(|edge| edge.weight().is_active)
+            .filter(|edge| component_ids[&edge.source()] == component_ids[&edge.target()])
+            .map(|edge| edge.id())
+            .collect()
+    }
+
+    /// Return the edges that are implied by other edges: A -> C is redundant
+    /// if A -> B -> ... -> C also exists. Duplicate edges are redundant too.
+    /// Edges inside a cycle are always kept, and inactive edges are neither
+    /// removed nor used as paths.
+    pub fn redundant_edges(&self) -> Vec<EdgeIndex> {
+        let active_graph = self.active_graph();
+
+        // Work on the components of the graph, so cycles become single nodes.
+        let components = petgraph::algo::tarjan_scc(&active_graph);
+        let mut component_ids = vec![0; active_graph.node_count()];
+        for (component_id, component) in components.iter().enumerate() {
+            for node_idx in component {
+                component_ids[node_idx.index()] = component_id;
+            }
+        }
+
+        let mut component_successors = vec![HashSet::new(); components.len()];
+        for edge in active_graph.edge_references() {
+            let src_component = component_ids[edge.source().index()];
+            let dst_component = component_ids[edge.target().index()];
+            if src_component != dst_component {
+                component_successors[src_component].insert(dst_component);
+            }
+        }
+
+        let mut redundant = Vec::new();
+        for (component_id, successors) in component_successors.iter().enumerate() {
+            // Mark everything reachable in two or more steps.
+            let mut indirect = HashSet::new();
+            let mut stack = successors.iter()
+                .flat_map(|succ| component_successors[*succ].iter().cloned())
+                .collect::<Vec<_>>();
+            while let Some(reached) = stack.pop() {
+                if indirect.insert(reached) {
+                    stack.extend(component_successors[reached].iter().cloned());
+                }
+            }
+
+            let mut direct = HashSet::new();
+            for node_idx in &components[component_id] {
+                for edge in self.graph.edges_directed(*node_idx, petgraph::Outgoing) {
+                    if !edge.weight().is_active {
+                        continue;
+                    }
+                    let dst_component = component_ids[edge.target().index()];
+                    let is_duplicate = !direct.insert(edge.target());
+                    if is_duplicate || (dst_component != component_id && indirect.contains(&dst_component)) {
+                        redundant.push(edge.id());
+                    }
+                }
+            }
+        }
+
+        redundant
+    }
+
+    /// Return a copy of the graph without redundant edges.
+    pub fn transitive_reduction(&self) -> HashGraph<T> {
+        let redundant = self.redundant_edges().into_iter().collect::<HashSet<_>>();
+
+        // filter_map() keeps node indices when every node is kept.
+        HashGraph {
+            graph: self.graph.filter_map(|_, node| Some(node.clone()), |edge_idx, weight| {
+                if redundant.contains(&edge_idx) { None } else { Some(weight.clone()) }
+            }),
+            indices: self.indices.clone(),
+        }
+    }
+
+    /// Return the transitive include cost of every node, in node index order.
+    /// Inactive edges are ignored. `file_size` gives the size in bytes of a
+    /// single node, and `is_translation_unit` picks the nodes that are
+    /// compiled on their own.
+    pub fn include_costs<F, U>(&self, file_size: F, is_translation_unit: U) -> Vec<IncludeCost>
+        where F: Fn(&T) -> u64,
+              U: Fn(&T) -> bool
+    {
+        // Only count active includes.
+        let active_graph = self.active_graph();
+
+        let sizes = self.graph.node_indices().map(|idx| file_size(&self.graph[idx])).collect::<Vec<_>>();
+
+        // Only included nodes can be reached, so give each of them a bit.
+        let mut bit_nodes = Vec::new();
+        let mut node_bits = vec![None; active_graph.node_count()];
+        for node_idx in active_graph.node_indices() {
+            if active_graph.neighbors_directed(node_idx, petgraph::Incoming).next().is_some() {
+                node_bits[node_idx.index()] = Some(bit_nodes.len());
+                bit_nodes.push(node_idx);
+            }
+        }
+
+        // Nodes in a cycle all reach the same files, so work on strongly
+        // connected components. tarjan_scc() returns them in reverse
+        // topological order, so every component comes after the ones it
+        // includes, and their reach sets can be reused.
+        let components = petgraph::algo::tarjan_scc(&active_graph);
+        let mut component_ids = vec![0; active_graph.node_count()];
+        for (component_id, component) in components.iter().enumerate() {
+            for node_idx in component {
+                component_ids[node_idx.index()] = component_id;
+            }
+        }
+
+        // Count the includes into each component from other components. Once
+        // the last of them has been visited, the component's reach set is
+        // dropped, so only the sets still waiting to be used are kept.
+        let mut pending_includes = vec![0; components.len()];
+        for edge in active_graph.edge_references() {
+            let (src_component, dst_component) =
+                (component_ids[edge.source().index()], component_ids[edge.target().index()]);
+            if src_component != dst_component {
+                pending_includes[dst_component] += 1;
+            }
+        }
+
+        let mut reach_sets: Vec<Option<BitSet>> = vec![None; components.len()];
+        let mut costs = self.graph
+            .node_indices()
+            .map(|idx| {
+                IncludeCost {
+                    node: idx,
+                    reach: 0,
+                    reach_bytes: 0,
+                    includers: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for (component_id, component) in components.iter().enumerate() {
+            let mut reach = BitSet::new(bit_nodes.len());
+            let mut units = 0;
+
+            for node_idx in component {
+                for neighbor_idx in active_graph.neighbors_directed(*node_idx, petgraph::Outgoing) {
+                    if let Some(bit) = node_bits[neighbor_idx.index()] {
+                        reach.insert(bit);
+                    }
+                    let neighbor_component = component_ids[neighbor_idx.index()];
+                    if neighbor_component != component_id {
+                        if let Some(ref neighbor_reach) = reach_sets[neighbor_component] {
+                            reach.union_with(neighbor_reach);
+                        }
+                        pending_includes[neighbor_component] -= 1;
+                        if pending_includes[neighbor_component] == 0 {
+                            reach_sets[neighbor_component] = None;
+                        }
+                    }
+                }
+                if is_translation_unit(&self.graph[*node_idx]) {
+                    units += 1;
+                }
+            }
+
+            let reach_bytes = reach.iter().map(|bit| sizes[bit_nodes[bit].index()]).sum::<u64>();
+            for node_idx in component {
+                // A node in a cycle includes itself, but that doesn't count.
+                let self_bit = node_bits[node_idx.index()].filter(|bit| reach.contains(*bit));
+                let cost = &mut costs[node_idx.index()];
+                cost.reach = reach.len() - self_bit.map_or(0, |_| 1);
+                cost.reach_bytes = reach_bytes - self_bit.map_or(0, |_| sizes[node_idx.index()]);
+            }
+
+            // Everything a translation unit reaches is compiled with it.
+            for bit in reach.iter() {
+                costs[bit_nodes[bit].index()].includers += units;
+            }
+            if pending_includes[component_id] > 0 {
+                reach_sets[component_id] = Some(reach);
+            }
+        }
+
+        costs
+    }
+
+    // The graph structure without inactive edges. Node indices are unchanged.
+    fn active_graph(&self) -> Graph<(), ()> {
+        self.graph.filter_map(|_, _| Some(()), |_, edge| if edge.is_active { Some(()) } else { None })
+    }
+
+    // Breadth-first search for the shortest cycle through [start_idx], only
+    // visiting nodes in [members] and following active edges.
+    fn shortest_cycle(&self,
+                      start_idx: NodeIndex,
+                      members: &HashSet<NodeIndex>)
+                      -> Option<Vec<NodeIndex>> {
+        let mut previous = HashMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(start_idx);
+
+        while let Some(node_idx) = queue.pop_front() {
+            let active_edges = self.graph
+                .edges_directed(node_idx, petgraph::Outgoing)
+                .filter(|edge| edge.weight().is_active);
+            for neighbor_idx in active_edges.map(|edge| edge.target()) {
+                if neighbor_idx == start_idx {
+                    // Walk the path back to the start.
+                    let mut cycle = vec![node_idx];
+                    let mut current_idx = node_idx;
+                    while current_idx != start_idx {
+                        current_idx = previous[&current_idx];
+                        cycle.push(current_idx);
+                    }
+                    cycle.reverse();
+                    return Some(cycle);
+                }
+                if members.contains(&neighbor_idx) && !previous.contains_key(&neighbor_idx) {
+                    previous.insert(neighbor_idx, node_idx);
+                    queue.push_back(neighbor_idx);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Return a list of all node indices satisfying a predicate, in index order.
     pub fn find<F>(&self, pred: F) -> Vec<NodeIndex>
         where F: Fn(&T) -> bool
     {
 
-        self.indices
-            .iter()
-            .filter(|&(node, _)| pred(node))
-            .map(|(_, index)| *index)
+        self.graph
+            .node_indices()
+            .filter(|idx| pred(&self.graph[*idx]))
             .collect::<Vec<_>>()
     }
 
-    pub fn add_edge(&mut self, src_node: T, dst_node: T) {
+    /// Add a node, if it isn't in the graph yet. Returns its index.
+    pub fn add_node(&mut self, node: T) -> NodeIndex {
+        self.require_node(node)
+    }
+
+    /// Add an edge between two nodes, for an include in `src_node`.
+    pub fn add_edge(&mut self, src_node: T, dst_node: T, edge: IncludeEdge) {
         let src_node_idx = self.require_node(src_node);
         let dst_node_idx = self.require_node(dst_node);
-        self.graph.add_edge(src_node_idx, dst_node_idx, true);
+        self.graph.add_edge(src_node_idx, dst_node_idx, edge);
     }
 
+    /// True if `node` is in the graph.
     pub fn contains_node(&self, node: &T) -> bool {
         self.indices.contains_key(node)
     }
 
+    /// Return the index of a node, if it is in the graph.
+    pub fn node_index(&self, node: &T) -> Option<NodeIndex> {
+        self.indices.get(node).cloned()
+    }
+
+    /// Return all nodes, in index order.
+    pub fn nodes(&self) -> impl Iterator<Item = &T> {
+        self.graph.node_indices().map(move |idx| &self.graph[idx])
+    }
+
+    /// Return the nodes directly included by `node`.
+    pub fn includes_of(&self, node: &T) -> Vec<&T> {
+        self.neighbors(node, petgraph::Outgoing)
+    }
+
+    /// Return the nodes that directly include `node`.
+    pub fn includers_of(&self, node: &T) -> Vec<&T> {
+        self.neighbors(node, petgraph::Incoming)
+    }
+
+    fn neighbors(&self, node: &T, direction: petgraph::Direction) -> Vec<&T> {
+        match self.node_index(node) {
+            Some(idx) => {
+                let mut neighbors = self.graph
+                    .neighbors_directed(idx, direction)
+                    .collect::<Vec<_>>();
+                // Parallel edges would list a neighbor twice.
+                neighbors.sort();
+                neighbors.dedup();
+                neighbors.into_iter().map(|idx| &self.graph[idx]).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
     // Insert node if it does not exist yet.
     fn require_node(&mut self, node: T) -> NodeIndex {
         if self.indices.contains_key(&node) {
@@ -149,3 +561,39 @@ impl<T: Eq + PartialEq + Hash + Clone + Display> HashGraph<T> {
         //            .or_insert_with(|| self.graph.add_node(node))
     }
 }
+
+// -----------------------------------------------------------------------------
+
+// Fixed-size set of small integers, used for memoized reachability.
+#[derive(Debug, Clone)]
+struct BitSet {
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    fn new(size: usize) -> BitSet {
+        BitSet { words: vec![0; size.div_ceil(64)] }
+    }
+
+    fn insert(&mut self, bit: usize) {
+        self.words[bit / 64] |= 1 << (bit % 64);
+    }
+
+    fn contains(&self, bit: usize) -> bool {
+        self.words[bit / 64] & (1 << (bit % 64)) != 0
+    }
+
+    fn union_with(&mut self, other: &BitSet) {
+        for (word, other_word) in self.words.iter_mut().zip(&other.words) {
+            *word |= *other_word;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.words.len() * 64).filter(move |bit| self.contains(*bit))
+    }
+}
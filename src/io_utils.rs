@@ -0,0 +1,8 @@
+use std::io;
+
+// ----------------------------------------------------------------------------
+
+// An error for a file that could be read, but doesn't hold what was expected.
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
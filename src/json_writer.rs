@@ -0,0 +1,207 @@
+
+use std::io::{self, Read, Write};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+
+use petgraph::visit::EdgeRef;
+use serde_json::{self, Value};
+
+use file_node::FileNode;
+use hash_graph::{HashGraph, IncludeEdge};
+use io_utils::invalid_data;
+use writer::GraphWriter;
+
+// Writes graphs as JSON, for scripts that want to read the graph directly.
+// Edges refer to nodes by their id. For example:
+//
+//     {
+//       "root": "/src",
+//       "nodes": [
+//         { "id": 0, "path": "/src/main.cpp", "is_system": false, "resolved": true, "kind": "source" },
+//         { "id": 1, "path": "vector", "is_system": true, "resolved": false, "kind": "header" },
+//         { "id": 2, "path": "config.h", "is_system": false, "resolved": false, "kind": "header",
+//           "includer_dir": "/src" }
+//       ],
+//       "edges": [
+//         { "source": 0, "target": 1, "line": 3, "include": "<vector>", "active": true }
+//       ]
+//     }
+//
+// The root is the directory that was scanned, if the writer was given one.
+// Unresolved includes keep the path as it was written. Unresolved "" includes
+// also have the directory of the including file.
+#[derive(Debug, Clone, Default)]
+pub struct JsonWriter {
+    root: Option<PathBuf>,
+}
+
+impl JsonWriter {
+    pub fn new() -> JsonWriter {
+        JsonWriter::default()
+    }
+
+    // Save the directory that was scanned, so the graph can be compared with
+    // a scan of another checkout.
+    pub fn root(mut self, root: &Path) -> JsonWriter {
+        self.root = Some(root.to_path_buf());
+        self
+    }
+}
+
+impl GraphWriter for JsonWriter {
+    fn write_graph(&self, out: &mut dyn Write, graph: &HashGraph<FileNode>) -> Result<(), io::Error> {
+        let mut json = graph_to_json(graph);
+        if let Some(ref root) = self.root {
+            json["root"] = json!(root.to_string_lossy());
+        }
+        serde_json::to_writer_pretty(&mut *out, &json)?;
+        writeln!(out)
+    }
+}
+
+// Convert the graph to a JSON value, in the format described above.
+pub fn graph_to_json(graph: &HashGraph<FileNode>) -> Value {
+    let nodes = graph.graph
+        .node_indices()
+        .map(|idx| {
+            let node = &graph.graph[idx];
+            let mut value = json!({
+                "id": idx.index(),
+                "path": node.path.to_string_lossy(),
+                "is_system": node.is_system,
+                "resolved": node.is_resolved(),
+                "kind": node.kind().name(),
+            });
+            if let Some(ref dir) = node.includer_dir {
+                value["includer_dir"] = json!(dir.to_string_lossy());
+            }
+            value
+        })
+        .collect::<Vec<_>>();
+
+    let edges = graph.graph
+        .edge_references()
+        .map(|edge| {
+            let include = edge.weight();
+            json!({
+                "source": edge.source().index(),
+                "target": edge.target().index(),
+                "line": include.line,
+                "include": include.spelling,
+                "active": include.is_active,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "nodes": nodes,
+        "edges": edges,
+    })
+}
+
+// Read a graph saved by JsonWriter, with the directory it was scanned from.
+pub fn load_json_graph(path: &Path) -> Result<(HashGraph<FileNode>, Option<PathBuf>), io::Error> {
+    let mut f = File::open(path)?;
+    let mut text = String::new();
+    f.read_to_string(&mut text)?;
+
+    let json: Value = serde_json::from_str(&text).map_err(|err| invalid_data(&err.to_string()))?;
+    Ok((graph_from_json(&json)?, root_from_json(&json)))
+}
+
+// The directory a graph saved as JSON was scanned from, if it was saved.
+pub fn root_from_json(json: &Value) -> Option<PathBuf> {
+    json.get("root").and_then(Value::as_str).map(PathBuf::from)
+}
+
+// Convert a JSON value, in the format described above, back into a graph.
+pub fn graph_from_json(json: &Value) -> Result<HashGraph<FileNode>, io::Error> {
+    let nodes = json.get("nodes")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid_data("Graph is missing \"nodes\""))?;
+    let edges = json.get("edges")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid_data("Graph is missing \"edges\""))?;
+
+    let mut graph = HashGraph::new();
+    let mut nodes_by_id = HashMap::new();
+    for node in nodes {
+        let id = node.get("id")
+            .and_then(Value::as_u64)
+            .ok_or_else(|| invalid_data("Node is missing \"id\""))?;
+        let path = node.get("path")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid_data("Node is missing \"path\""))?;
+        let is_system = node.get("is_system").and_then(Value::as_bool).unwrap_or(false);
+
+        let file_node = FileNode {
+            includer_dir: node.get("includer_dir").and_then(Value::as_str).map(PathBuf::from),
+            ..FileNode::new(path, is_system)
+        };
+        graph.add_node(file_node.clone());
+        nodes_by_id.insert(id, file_node);
+    }
+
+    for edge in edges {
+        let node_for = |key: &str| {
+            edge.get(key)
+                .and_then(Value::as_u64)
+                .and_then(|id| nodes_by_id.get(&id))
+                .cloned()
+                .ok_or_else(|| invalid_data(&format!("Edge has no valid \"{}\"", key)))
+        };
+        let src_node = node_for("source")?;
+        let dst_node = node_for("target")?;
+
+        graph.add_edge(src_node,
+                       dst_node,
+                       IncludeEdge {
+                           is_active: edge.get("active").and_then(Value::as_bool).unwrap_or(true),
+                           line: edge.get("line").and_then(Value::as_u64).unwrap_or(0) as usize,
+                           spelling: edge.get("include").and_then(Value::as_str).unwrap_or("").to_string(),
+                       });
+    }
+
+    Ok(graph)
+}
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_write_json() {
+    let mut graph = HashGraph::new();
+    graph.add_edge(FileNode::new("/src/main.cpp", false),
+                   FileNode::new("vector", true),
+                   IncludeEdge {
+                       is_active: true,
+                       line: 3,
+                       spelling: String::from("<vector>"),
+                   });
+    graph.add_edge(FileNode::new("/src/main.cpp", false),
+                   FileNode { includer_dir: Some(PathBuf::from("/src")), ..FileNode::new("config.h", false) },
+                   IncludeEdge {
+                       is_active: true,
+                       line: 4,
+                       spelling: String::from("\"config.h\""),
+                   });
+
+    let mut out = Vec::new();
+    JsonWriter::new().root(Path::new("/src")).write_graph(&mut out, &graph).unwrap();
+    let json: Value = serde_json::from_slice(&out).unwrap();
+
+    assert_eq!(root_from_json(&json), Some(PathBuf::from("/src")));
+
+    assert_eq!(json["nodes"][0],
+               json!({"id": 0, "path": "/src/main.cpp", "is_system": false, "resolved": true, "kind": "source"}));
+    assert_eq!(json["nodes"][1],
+               json!({"id": 1, "path": "vector", "is_system": true, "resolved": false, "kind": "header"}));
+    assert_eq!(json["nodes"][2]["includer_dir"], json!("/src"));
+    assert_eq!(json["edges"][0],
+               json!({"source": 0, "target": 1, "line": 3, "include": "<vector>", "active": true}));
+
+    // Reading the JSON back gives the same graph.
+    let read_back = graph_from_json(&json).unwrap();
+    assert_eq!(read_back.nodes().collect::<Vec<_>>(), graph.nodes().collect::<Vec<_>>());
+    assert_eq!(read_back.graph.raw_edges()[0].weight, graph.graph.raw_edges()[0].weight);
+}
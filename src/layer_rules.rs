@@ -0,0 +1,218 @@
+
+use std::io::{self, Read};
+use std::fs::File;
+use std::path::Path;
+
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
+use petgraph::prelude::EdgeIndex;
+use petgraph::visit::EdgeRef;
+
+use file_node::FileNode;
+use hash_graph::HashGraph;
+
+// ----------------------------------------------------------------------------
+
+// Whether a rule lists the files a layer may not include, or the only ones
+// it may include.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    MayNotInclude,
+    MayOnlyInclude,
+}
+
+// A single architecture rule, e.g. "core/** may not include ui/**".
+#[derive(Debug, Clone)]
+pub struct LayerRule {
+    pub text: String, // The rule as written.
+    pub kind: RuleKind,
+    layer: GlobSet,
+    targets: GlobSet,
+}
+
+impl LayerRule {
+    // Parse a rule of the form "<glob> may not include <globs>" or
+    // "<glob> may only include <globs>". Several target globs are separated
+    // by commas or spaces.
+    pub fn parse(text: &str) -> Result<LayerRule, String> {
+        let text = text.trim();
+        let (layer, kind, targets) = if let Some(pos) = text.find(" may not include ") {
+            (&text[..pos], RuleKind::MayNotInclude, &text[pos + " may not include ".len()..])
+        } else if let Some(pos) = text.find(" may only include ") {
+            (&text[..pos], RuleKind::MayOnlyInclude, &text[pos + " may only include ".len()..])
+        } else {
+            return Err(format!("Expected \"may not include\" or \"may only include\" in rule: {}", text));
+        };
+
+        Ok(LayerRule {
+            text: String::from(text),
+            kind,
+            layer: build_glob_set(layer.split_whitespace())?,
+            targets: build_glob_set(targets.split(|c: char| c == ',' || c.is_whitespace()))?,
+        })
+    }
+
+    // True if an include from [src] to [dst] breaks this rule. Paths are
+    // relative to the project root. "may only include" rules only apply to
+    // files inside the project, so system headers can still be included.
+    fn is_broken_by(&self, src: &Path, dst: &Path, dst_in_project: bool) -> bool {
+        if !self.layer.is_match(src) {
+            return false;
+        }
+        match self.kind {
+            RuleKind::MayNotInclude => self.targets.is_match(dst),
+            RuleKind::MayOnlyInclude => dst_in_project && !self.targets.is_match(dst),
+        }
+    }
+}
+
+// An include that breaks a layering rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleViolation {
+    pub edge: EdgeIndex,
+    pub rule: String, // The first rule the include breaks, as written.
+}
+
+// A list of layering rules, checked against every include in a graph. Rules
+// files have one rule per line, e.g.
+//
+//     # The core doesn't know about the UI.
+//     core/** may not include ui/**
+//     ui/** may only include ui/**, core/**
+//
+// Globs are matched against paths relative to the project root. '*' doesn't
+// match '/', and '**' matches any number of directories.
+#[derive(Debug, Clone, Default)]
+pub struct LayerRules {
+    pub rules: Vec<LayerRule>,
+}
+
+impl LayerRules {
+    // Read a rules file.
+    pub fn load(path: &Path) -> Result<LayerRules, io::Error> {
+        let mut f = File::open(path)?;
+        let mut text = String::new();
+        f.read_to_string(&mut text)?;
+
+        LayerRules::parse(&text)
+    }
+
+    // Parse the text of a rules file. Empty lines and lines starting with '#'
+    // are skipped.
+    pub fn parse(text: &str) -> Result<LayerRules, io::Error> {
+        let mut rules = Vec::new();
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let rule = LayerRule::parse(line)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", line_idx + 1, err)))?;
+            rules.push(rule);
+        }
+
+        Ok(LayerRules { rules })
+    }
+
+    // Check every include in the graph against the rules. Returns the
+    // includes that break a rule, in edge order.
+    pub fn check(&self, graph: &HashGraph<FileNode>, root_dir: &Path) -> Vec<RuleViolation> {
+        let relative = |node: &FileNode| {
+            let in_project = node.is_resolved() && node.path.starts_with(root_dir);
+            let path = node.path.strip_prefix(root_dir).unwrap_or(&node.path).to_path_buf();
+            (path, in_project)
+        };
+
+        graph.graph
+            .edge_references()
+            .filter_map(|edge| {
+                let (src_path, _) = relative(&graph.graph[edge.source()]);
+                let (dst_path, dst_in_project) = relative(&graph.graph[edge.target()]);
+                self.rules
+                    .iter()
+                    .find(|rule| rule.is_broken_by(&src_path, &dst_path, dst_in_project))
+                    .map(|rule| {
+                        RuleViolation {
+                            edge: edge.id(),
+                            rule: rule.text.clone(),
+                        }
+                    })
+            })
+            .collect()
+    }
+}
+
+// Compile a list of globs into one set. Empty strings are skipped.
+fn build_glob_set<'a, I>(globs: I) -> Result<GlobSet, String>
+    where I: IntoIterator<Item = &'a str>
+{
+    let mut builder = GlobSetBuilder::new();
+    let mut count = 0;
+    for glob in globs.into_iter().filter(|glob| !glob.is_empty()) {
+        builder.add(compile_glob(glob)?);
+        count += 1;
+    }
+    if count == 0 {
+        return Err(String::from("Rule is missing a file pattern"));
+    }
+    builder.build().map_err(|err| err.to_string())
+}
+
+// Compile a glob where '*' stays within a directory, like in .gitignore files.
+fn compile_glob(glob: &str) -> Result<Glob, String> {
+    GlobBuilder::new(glob)
+        .literal_separator(true)
+        .build()
+        .map_err(|err| err.to_string())
+}
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_check_rules() {
+    use hash_graph::IncludeEdge;
+
+    let rules = LayerRules::parse("# Layers\n\
+                                   core/** may not include ui/**\n\
+                                   \n\
+                                   ui/* may only include ui/*, core/*.h\n")
+        .unwrap();
+    assert_eq!(rules.rules.len(), 2);
+    assert_eq!(rules.rules[1].kind, RuleKind::MayOnlyInclude);
+
+    let mut graph = HashGraph::new();
+    let mut include = |src: &str, dst: &str| {
+        graph.add_edge(FileNode::new(src, false),
+                       FileNode::new(dst, false),
+                       IncludeEdge {
+                           is_active: true,
+                           line: 1,
+                           spelling: String::new(),
+                       })
+    };
+    include("/project/core/sub/a.h", "/project/ui/button.h");
+    include("/project/core/b.h", "/project/core/sub/a.h");
+    include("/project/ui/button.h", "/project/core/b.h");
+    include("/project/ui/button.h", "/project/core/sub/a.h");
+    include("/project/ui/button.h", "vector");
+
+    let violations = rules.check(&graph, Path::new("/project"));
+    let broken = violations.iter()
+        .map(|violation| {
+            let (src_idx, dst_idx) = graph.graph.edge_endpoints(violation.edge).unwrap();
+            (graph.graph[src_idx].path.to_str().unwrap(),
+             graph.graph[dst_idx].path.to_str().unwrap(),
+             violation.rule.as_str())
+        })
+        .collect::<Vec<_>>();
+
+    // '*' doesn't cross directories, and files outside the project can always
+    // be included.
+    assert_eq!(broken,
+               vec![("/project/core/sub/a.h", "/project/ui/button.h", "core/** may not include ui/**"),
+                    ("/project/ui/button.h",
+                     "/project/core/sub/a.h",
+                     "ui/* may only include ui/*, core/*.h")]);
+
+    let err = LayerRules::parse("core/** includes ui/**").unwrap_err();
+    assert!(err.to_string().starts_with("line 1: "));
+}
@@ -0,0 +1,340 @@
+// A preprocessor directive line, e.g. '#include <vector>'.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Directive {
+    pub name: String, // Directive name without the '#', e.g. "include".
+    pub args: String, // Rest of the line, with comments removed and continuations joined.
+    pub line: usize, // 1-based line of the '#'.
+    pub column: usize, // 1-based column of the '#'.
+}
+
+impl Directive {
+    // For an include directive, return the header name and whether it is a
+    // system (<>) include. Computed includes (#include MACRO) return None.
+    pub fn include_name(&self) -> Option<(&str, bool)> {
+        let args = self.args.trim();
+        let (close, is_system) = match args.chars().next() {
+            Some('<') => ('>', true),
+            Some('"') => ('"', false),
+            _ => return None,
+        };
+        args[1..].find(close).map(|end| (&args[1..end + 1], is_system))
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// A character after line splicing, with its position in the original file.
+#[derive(Debug, Clone, Copy)]
+struct SourceChar {
+    c: char,
+    line: usize,
+    column: usize,
+}
+
+// Remove backslash-newline continuations, keeping the original position of
+// every remaining character.
+fn splice_lines(text: &str) -> Vec<SourceChar> {
+    let mut chars = Vec::new();
+    let mut line = 1;
+    let mut column = 1;
+
+    let mut iter = text.chars().peekable();
+    while let Some(c) = iter.next() {
+        if c == '\\' {
+            let mut lookahead = iter.clone();
+            if lookahead.peek() == Some(&'\r') {
+                lookahead.next();
+            }
+            if lookahead.peek() == Some(&'\n') {
+                lookahead.next();
+                iter = lookahead;
+                line += 1;
+                column = 1;
+                continue;
+            }
+        }
+
+        chars.push(SourceChar {
+            c,
+            line,
+            column,
+        });
+
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        } else {
+            column += 1;
+        }
+    }
+
+    chars
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// Prefixes that start a character literal or raw string, e.g. L'a' or u8R"(...)".
+const CHAR_PREFIXES: [&str; 5] = ["", "L", "u", "U", "u8"];
+const RAW_STRING_PREFIXES: [&str; 5] = ["R", "LR", "uR", "UR", "u8R"];
+
+// ----------------------------------------------------------------------------
+
+// Return every preprocessor directive in a C/C++ source file. Directives
+// inside comments, string literals and raw string literals are skipped.
+pub fn scan_directives(text: &str) -> Vec<Directive> {
+    let chars = splice_lines(text);
+    let mut directives = Vec::new();
+
+    let mut pos = 0;
+    let mut at_line_start = true; // Only whitespace or comments seen on this line.
+    let mut word = String::new(); // Current identifier or number, to detect literal prefixes.
+
+    while pos < chars.len() {
+        let c = chars[pos].c;
+        let next = chars.get(pos + 1).map(|sc| sc.c);
+
+        match c {
+            '\n' => {
+                at_line_start = true;
+                word.clear();
+                pos += 1;
+            }
+            '/' if next == Some('/') => {
+                pos = skip_line_comment(&chars, pos);
+            }
+            '/' if next == Some('*') => {
+                word.clear();
+                pos = skip_block_comment(&chars, pos);
+            }
+            '#' if at_line_start => {
+                let (directive, end) = read_directive(&chars, pos);
+                directives.extend(directive);
+                pos = end;
+            }
+            '"' => {
+                pos = if RAW_STRING_PREFIXES.contains(&word.as_str()) {
+                    skip_raw_string(&chars, pos)
+                } else {
+                    skip_quoted(&chars, pos)
+                };
+                at_line_start = false;
+                word.clear();
+            }
+            '\'' if CHAR_PREFIXES.contains(&word.as_str()) => {
+                pos = skip_quoted(&chars, pos);
+                at_line_start = false;
+                word.clear();
+            }
+            '\'' => {
+                // Digit separator, e.g. 1'000'000.
+                pos += 1;
+            }
+            c if c.is_whitespace() => {
+                word.clear();
+                pos += 1;
+            }
+            c if is_identifier_char(c) => {
+                word.push(c);
+                at_line_start = false;
+                pos += 1;
+            }
+            _ => {
+                word.clear();
+                at_line_start = false;
+                pos += 1;
+            }
+        }
+    }
+
+    directives
+}
+
+// Skip a '//' comment, stopping at the newline.
+fn skip_line_comment(chars: &[SourceChar], start: usize) -> usize {
+    chars[start..].iter().position(|sc| sc.c == '\n').map_or(chars.len(), |len| start + len)
+}
+
+// Skip a '/* */' comment, returning the position after the closing '*/'.
+fn skip_block_comment(chars: &[SourceChar], start: usize) -> usize {
+    let mut pos = start + 2;
+    while pos + 1 < chars.len() {
+        if chars[pos].c == '*' && chars[pos + 1].c == '/' {
+            return pos + 2;
+        }
+        pos += 1;
+    }
+    chars.len()
+}
+
+// Skip a string or character literal, returning the position after the
+// closing quote. Unterminated literals stop at the end of the line.
+fn skip_quoted(chars: &[SourceChar], start: usize) -> usize {
+    let quote = chars[start].c;
+    let mut pos = start + 1;
+    while pos < chars.len() {
+        match chars[pos].c {
+            '\\' => pos += 2,
+            '\n' => return pos,
+            c if c == quote => return pos + 1,
+            _ => pos += 1,
+        }
+    }
+    chars.len()
+}
+
+// Skip a raw string literal R"delim( ... )delim", starting at the quote.
+fn skip_raw_string(chars: &[SourceChar], start: usize) -> usize {
+    let delimiter = chars[start + 1..]
+        .iter()
+        .take_while(|sc| sc.c != '(')
+        .map(|sc| sc.c)
+        .collect::<String>();
+
+    // Not a valid raw string, so read it as a normal one.
+    if delimiter.len() > 16 || delimiter.contains(|c: char| c.is_whitespace() || c == '"') {
+        return skip_quoted(chars, start);
+    }
+
+    let terminator = format!("){}\"", delimiter).chars().collect::<Vec<_>>();
+    let mut pos = start + delimiter.len() + 2;
+    while pos + terminator.len() <= chars.len() {
+        if chars[pos..pos + terminator.len()].iter().map(|sc| sc.c).eq(terminator.iter().cloned()) {
+            return pos + terminator.len();
+        }
+        pos += 1;
+    }
+    chars.len()
+}
+
+// Read a directive starting at the '#'. Returns the directive (if the line
+// has a name) and the position of the newline that ends it.
+fn read_directive(chars: &[SourceChar], start: usize) -> (Option<Directive>, usize) {
+    let mut pos = start + 1;
+
+    // Skip whitespace and comments between '#' and the directive name.
+    loop {
+        match (chars.get(pos).map(|sc| sc.c), chars.get(pos + 1).map(|sc| sc.c)) {
+            (Some('/'), Some('*')) => pos = skip_block_comment(chars, pos),
+            (Some(c), _) if c != '\n' && c.is_whitespace() => pos += 1,
+            _ => break,
+        }
+    }
+
+    let mut name = String::new();
+    while pos < chars.len() && is_identifier_char(chars[pos].c) {
+        name.push(chars[pos].c);
+        pos += 1;
+    }
+    let is_include = name == "include" || name == "include_next";
+
+    let mut args = String::new();
+    while pos < chars.len() && chars[pos].c != '\n' {
+        let c = chars[pos].c;
+        let next = chars.get(pos + 1).map(|sc| sc.c);
+
+        let end = match c {
+            '/' if next == Some('/') => skip_line_comment(chars, pos),
+            '/' if next == Some('*') => {
+                args.push(' ');
+                skip_block_comment(chars, pos)
+            }
+            '"' | '\'' => {
+                let end = skip_quoted(chars, pos);
+                args.extend(chars[pos..end].iter().map(|sc| sc.c));
+                end
+            }
+            '<' if is_include && args.trim().is_empty() => {
+                // Header names are taken literally, so 'a//b.h' is not a comment.
+                let len = chars[pos..]
+                    .iter()
+                    .position(|sc| sc.c == '>' || sc.c == '\n')
+                    .unwrap_or(chars.len() - pos);
+                let end = if chars.get(pos + len).map(|sc| sc.c) == Some('>') {
+                    pos + len + 1
+                } else {
+                    pos + len
+                };
+                args.extend(chars[pos..end].iter().map(|sc| sc.c));
+                end
+            }
+            _ => {
+                args.push(c);
+                pos + 1
+            }
+        };
+        pos = end;
+    }
+
+    if name.is_empty() {
+        return (None, pos);
+    }
+
+    let directive = Directive {
+        name,
+        args: args.trim().to_string(),
+        line: chars[start].line,
+        column: chars[start].column,
+    };
+    (Some(directive), pos)
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn include_names(text: &str) -> Vec<String> {
+        scan_directives(text)
+            .iter()
+            .filter_map(|d| d.include_name())
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn skip_comments_and_strings() {
+        let text = r##"
+#include "a.h" // see <b>
+/* #include "block.h"
+#include "block2.h" */
+// #include "line.h" \
+   #include "continued_comment.h"
+const char* s = "#include \"string.h\"";
+const char* r = R"x(
+#include "raw.h"
+)x";
+char c = '"'; int n = 1'000;
+#include <c.h>
+"##;
+        assert_eq!(include_names(text), vec!["a.h", "c.h"]);
+    }
+
+    #[test]
+    fn directive_positions() {
+        let text = "int x;\n  #  include /* note */ <vector> // comment\n#define A \\\n  1\n#if A\n";
+        let directives = scan_directives(text);
+        assert_eq!(directives.len(), 3);
+
+        assert_eq!(directives[0].name, "include");
+        assert_eq!(directives[0].include_name(), Some(("vector", true)));
+        assert_eq!((directives[0].line, directives[0].column), (2, 3));
+
+        assert_eq!(directives[1].name, "define");
+        assert_eq!(directives[1].args, "A   1");
+
+        // Line numbers stay correct after a continuation.
+        assert_eq!(directives[2].name, "if");
+        assert_eq!((directives[2].line, directives[2].column), (5, 1));
+    }
+
+    #[test]
+    fn computed_includes() {
+        let directives = scan_directives("#include HEADER\n#include \"a//b.h\"\n");
+        assert_eq!(directives[0].include_name(), None);
+        assert_eq!(directives[1].include_name(), Some(("a//b.h", false)));
+    }
+}
@@ -0,0 +1,84 @@
+
+//! Library for building and analyzing include graphs of C and C++ code.
+//!
+//! A `Scanner` reads source files into a `HashGraph` of `FileNode`s, which can
+//! then be filtered, merged and queried, and written out with a `GraphWriter`.
+//! Problems found along the way are returned with the graph, not printed:
+//!
+//! ```no_run
+//! # use std::io;
+//! # use std::path::Path;
+//! # use include2dot::{DotWriter, GraphWriter, Scanner, SearchPaths};
+//! # fn main() -> Result<(), io::Error> {
+//! # let search_paths = SearchPaths::default();
+//! let scan = Scanner::new()
+//!     .search_paths(search_paths)
+//!     .scan_tree(Path::new("src"));
+//! for error in &scan.errors {
+//!     eprintln!("{}", error);
+//! }
+//!
+//! let cycles = scan.graph.find_cycles();
+//! DotWriter::new().write_graph(&mut io::stdout(), &scan.graph)?;
+//! # Ok(())
+//! # }
+//! ```
+
+extern crate petgraph;
+extern crate itertools;
+extern crate globset;
+extern crate ignore;
+extern crate rayon;
+
+extern crate regex;
+extern crate toml;
+#[macro_use]
+extern crate serde_json;
+
+pub mod path_utils;
+pub mod dot_writer;
+pub mod file_node;
+pub mod gexf_writer;
+pub mod graph_diff;
+pub mod graphml_writer;
+pub mod hash_graph;
+pub mod layer_rules;
+pub mod json_writer;
+pub mod mermaid_writer;
+pub mod path_filter;
+pub mod plantuml_writer;
+pub mod writer;
+
+pub mod compile_commands;
+pub mod config;
+mod io_utils;
+mod lexer;
+pub mod preprocessor;
+pub mod scan_cache;
+pub mod system_includes;
+
+pub mod find_includes;
+
+// -----------------------------------------------------------------------------
+
+pub use compile_commands::CompileCommand;
+pub use config::Config;
+pub use dot_writer::DotWriter;
+pub use file_node::{FileKind, FileNode};
+pub use find_includes::{QuoteTypes, ScanError, ScanResult, Scanner, UnresolvedInclude};
+pub use gexf_writer::GexfWriter;
+pub use graph_diff::{GraphDiff, ReachChange};
+pub use graphml_writer::GraphMlWriter;
+pub use hash_graph::{HashGraph, IncludeCost, IncludeEdge};
+pub use json_writer::JsonWriter;
+pub use layer_rules::{LayerRules, RuleViolation};
+pub use mermaid_writer::MermaidWriter;
+pub use path_filter::PathFilter;
+pub use plantuml_writer::PlantUmlWriter;
+pub use path_utils::SearchPaths;
+pub use preprocessor::MacroMap;
+pub use scan_cache::ScanCache;
+pub use writer::GraphWriter;
+
+/// The include graph produced by a Scanner.
+pub type IncludeGraph = HashGraph<FileNode>;
@@ -1,36 +1,25 @@
 
 #[macro_use]
 extern crate clap;
-use clap::{Arg, App};
+use clap::{Arg, App, ArgMatches, SubCommand};
 
-use std::path::PathBuf;
-use std::ffi::OsString;
-use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::ffi::OsStr;
 use std::env;
-use std::collections::HashSet;
+use std::collections::HashMap;
 
-use std::process::Command;
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Write};
 
-extern crate petgraph;
-extern crate walkdir;
-extern crate itertools;
-
-
-#[macro_use]
-extern crate lazy_static;
 extern crate regex;
-
 use regex::Regex;
 
-mod path_utils;
-mod dot_writer;
-mod file_node;
-mod hash_graph;
-
-mod find_includes;
-use find_includes::find_includes_in_tree;
+extern crate include2dot;
+use include2dot::{compile_commands, dot_writer, json_writer, preprocessor, system_includes};
+use include2dot::{CompileCommand, Config, DotWriter, FileKind, FileNode, GexfWriter, GraphDiff, GraphMlWriter,
+                  GraphWriter, HashGraph, JsonWriter, LayerRules, MacroMap, MermaidWriter, PathFilter,
+                  PlantUmlWriter, QuoteTypes, RuleViolation, ScanError, Scanner, SearchPaths,
+                  UnresolvedInclude};
 
 
 // -----------------------------------------------------------------------------
@@ -64,16 +53,427 @@ arg_enum! {
     }
 }
 
-arg_enum! {
-    #[derive(Debug)]
-    #[allow(non_camel_case_types)]
-    enum QuoteTypes {
-        both,
-        angle,
-        quote
+// Output formats written without graphviz.
+const DIRECT_FORMATS: [&str; 6] = ["dot", "json", "graphml", "gexf", "mermaid", "plantuml"];
+
+// Convert search paths from the command line or config into absolute paths.
+fn absolute_search_paths<I, P>(values: I) -> Vec<PathBuf>
+    where I: IntoIterator<Item = P>,
+          P: AsRef<Path>
+{
+    let mut search_paths = Vec::new();
+    for value in values {
+        let path = value.as_ref();
+
+        if let Ok(absolute_path) = std::fs::canonicalize(path) {
+            eprintln!("Using search path: {}", absolute_path.display());
+            search_paths.push(absolute_path);
+        } else {
+            eprintln!("Unable to find absolute include path from: {}", path.display());
+        }
+
+    }
+    search_paths
+}
+
+// The value of an option, taken from the command line if it was given there,
+// then from the config file, and then from the option's default.
+fn option_value<'a>(args: &'a ArgMatches, name: &str, config_value: &'a Option<String>) -> Option<&'a str> {
+    if args.occurrences_of(name) > 0 {
+        return args.value_of(name);
+    }
+    config_value.as_deref().or_else(|| args.value_of(name))
+}
+
+// A setting that is off by default, but can be turned on in the config. Both
+// --[name] and --no-[name] on the command line win over the config.
+fn flag_value(args: &ArgMatches, name: &str, config_value: Option<bool>) -> bool {
+    if args.is_present(name) {
+        true
+    } else if args.is_present(format!("no-{}", name)) {
+        false
+    } else {
+        config_value.unwrap_or(false)
+    }
+}
+
+// Read the project config: the --config file, or the nearest include2dot.toml
+// above the source directory. Without one, every option keeps its default.
+fn load_config(args: &ArgMatches, root_dir: &Path) -> Config {
+    if args.is_present("no-config") {
+        return Config::default();
+    }
+
+    let path = match args.value_of("config") {
+        Some(path) => PathBuf::from(path),
+        None => {
+            let src_dir = std::fs::canonicalize(root_dir).unwrap_or_else(|_| root_dir.to_path_buf());
+            match Config::find(&src_dir) {
+                Some(path) => path,
+                None => return Config::default(),
+            }
+        }
+    };
+
+    match Config::load(&path) {
+        Ok(config) => {
+            eprintln!("Using config: {}", path.display());
+            config
+        }
+        Err(err) => {
+            eprintln!("Unable to read config {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Report the files and directories that couldn't be scanned.
+fn print_scan_errors(errors: &[ScanError]) {
+    for error in errors {
+        eprintln!("{}", error);
+    }
+}
+
+// Report the includes that couldn't be found, with where they were searched for.
+fn print_unresolved_includes(unresolved: &[UnresolvedInclude]) {
+    if unresolved.is_empty() {
+        return;
+    }
+
+    eprintln!("Unable to locate {} includes:", unresolved.len());
+    for include in unresolved {
+        let searched = include.searched
+            .iter()
+            .map(|dir| dir.display().to_string())
+            .collect::<Vec<_>>();
+        eprintln!("  {}:{}:{}: {}",
+                  include.file.display(),
+                  include.line,
+                  include.column,
+                  include.spelling);
+        eprintln!("    searched: {}",
+                  if searched.is_empty() { String::from("(no directories)") } else { searched.join(", ") });
+    }
+}
+
+// Print a table of the nodes with the highest transitive include cost.
+fn print_include_costs(hash_graph: &HashGraph<FileNode>, limit: usize) {
+    let file_size = |node: &FileNode| std::fs::metadata(&node.path).map(|m| m.len()).unwrap_or(0);
+
+    let is_source = |node: &FileNode| node.kind() == FileKind::Source;
+
+    let mut costs = hash_graph.include_costs(file_size, is_source)
+        .into_iter()
+        .filter(|cost| cost.cost() > 0)
+        .collect::<Vec<_>>();
+    costs.sort_by(|a, b| {
+        b.cost()
+            .cmp(&a.cost())
+            .then_with(|| hash_graph.graph[a.node].path.cmp(&hash_graph.graph[b.node].path))
+    });
+
+    eprintln!("{:>10} {:>8} {:>12} {:>10}  File", "Cost", "Reach", "Bytes", "Includers");
+    for cost in costs.iter().take(limit) {
+        eprintln!("{:>10} {:>8} {:>12} {:>10}  {}",
+                  cost.cost(),
+                  cost.reach,
+                  cost.reach_bytes,
+                  cost.includers,
+                  hash_graph.graph[cost.node].path.display());
+    }
+}
+
+// List the includes that break a layering rule.
+fn print_rule_violations(hash_graph: &HashGraph<FileNode>, violations: &[RuleViolation]) {
+    eprintln!("Found {} layering violations.", violations.len());
+    for violation in violations {
+        let (src_idx, _) = hash_graph.graph.edge_endpoints(violation.edge).unwrap();
+        let include = &hash_graph.graph[violation.edge];
+        eprintln!("  {}:{}: {} breaks \"{}\"",
+                  hash_graph.graph[src_idx].path.display(),
+                  include.line,
+                  include.spelling,
+                  violation.rule);
     }
 }
 
+// Collect the type of includes to scan (<> vs "")
+fn quote_types(args: &ArgMatches, config: &Config) -> QuoteTypes {
+    match config.quote_types {
+        Some(quote_types) if args.occurrences_of("quotetypes") == 0 => quote_types,
+        _ => value_t!(args, "quotetypes", QuoteTypes).unwrap_or(QuoteTypes::Both),
+    }
+}
+
+// Running the compiler is only worth it when angle includes are resolved.
+fn detect_system_includes(args: &ArgMatches, config: &Config) -> bool {
+    !args.is_present("no-system-includes") && quote_types(args, config) != QuoteTypes::Quote
+}
+
+// Ask a compiler for its system include paths, falling back to the INCLUDE
+// environment variable.
+fn compiler_include_paths(compiler: &str) -> Vec<PathBuf> {
+    system_includes::detect_system_include_paths(compiler).unwrap_or_else(|err| {
+        eprintln!("Unable to detect system include paths from {:?}: {}", compiler, err);
+        system_includes::include_paths_from_env()
+    })
+}
+
+// Compilation databases don't list the compiler's built-in directories, so
+// ask the compiler of each unit, or --compiler if it was given, for them.
+// They are searched after the unit's own -I and -isystem paths.
+fn add_builtin_include_paths(args: &ArgMatches, commands: &mut [CompileCommand]) {
+    let mut paths_by_compiler = HashMap::new();
+    for command in commands {
+        let compiler = if args.occurrences_of("compiler") > 0 || command.compiler.is_empty() {
+            String::from(args.value_of("compiler").unwrap_or("cc"))
+        } else {
+            command.compiler.clone()
+        };
+        command.builtin_paths = paths_by_compiler.entry(compiler)
+            .or_insert_with_key(|compiler| compiler_include_paths(compiler))
+            .clone();
+    }
+}
+
+// Set up a scanner from the command line options, falling back to the config.
+fn build_scanner(args: &ArgMatches, config: &Config) -> Scanner {
+    // Collect a list of include paths to search.
+    let mut search_paths = SearchPaths {
+        quote: match args.values_of("iquote") {
+            Some(values) => absolute_search_paths(values),
+            None => absolute_search_paths(&config.iquote),
+        },
+        angle: match args.values_of("include") {
+            Some(values) => absolute_search_paths(values),
+            None => absolute_search_paths(&config.include),
+        },
+    };
+
+    // Add the compiler's default system include paths. Units from a
+    // compilation database get the paths of their own compiler instead.
+    let quote_types = quote_types(args, config);
+    if detect_system_includes(args, config) && !args.is_present("compile-commands") {
+        search_paths.angle.extend(compiler_include_paths(args.value_of("compiler").unwrap_or("cc")));
+    }
+
+    // Collect macros defined in the config, then on the command line, so the
+    // command line wins.
+    let mut defines = MacroMap::new();
+    let definitions = config.defines
+        .iter()
+        .map(String::as_str)
+        .chain(args.values_of("define").into_iter().flatten());
+    for definition in definitions {
+        let (name, value) = preprocessor::parse_define(definition);
+        defines.insert(name, value);
+    }
+
+    let mut scanner = Scanner::new()
+        .search_paths(search_paths)
+        .quote_types(quote_types)
+        .defines(defines)
+        .keep_conditional(args.is_present("conditional"))
+        .sniff_content(flag_value(args, "sniff", config.sniff))
+        .respect_ignore_files(flag_value(args, "respect-ignore", config.respect_ignore))
+        .scan_hidden(flag_value(args, "hidden", config.hidden))
+        .follow_links(flag_value(args, "follow-links", config.follow_links))
+        .jobs(value_t!(args, "jobs", usize).unwrap_or(0));
+
+    // Extensions given on the command line replace the ones in the config.
+    if let Some(extensions) = args.values_of("ext") {
+        scanner = scanner.extensions(extensions.map(|ext| ext.trim_start_matches('.')));
+    } else if let Some(ref extensions) = config.extensions {
+        scanner = scanner.extensions(extensions.iter().map(String::as_str));
+    }
+
+    // Regular expression of files to exclude. Skip if exclude string is empty.
+    if let Some(regex_str) = option_value(args, "exclude", &config.exclude) {
+        let exclude_regex = Regex::new(regex_str)
+            .unwrap_or_else(|err| panic!("Unable to parse exclude regex: {}", err));
+        scanner = scanner.exclude(exclude_regex);
+    }
+
+    // Command line globs are added after the ones from the config. See
+    // PathFilter for how '!' globs work.
+    let include_globs = config.include_globs
+        .iter()
+        .map(String::as_str)
+        .chain(args.values_of("include-glob").into_iter().flatten());
+    let exclude_globs = config.exclude_globs
+        .iter()
+        .map(String::as_str)
+        .chain(args.values_of("exclude-glob").into_iter().flatten());
+    match PathFilter::new(include_globs, exclude_globs) {
+        Ok(path_filter) => scanner = scanner.path_filter(path_filter),
+        Err(err) => {
+            eprintln!("Unable to parse path glob: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(cache_file) = args.value_of("cache") {
+        scanner = scanner.cache_file(PathBuf::from(cache_file));
+    }
+
+    scanner
+}
+
+// Write [bytes] to the file at [path], or to stdout if [path] is '-'.
+fn write_output(path: &str, bytes: &[u8]) -> Result<(), io::Error> {
+    if path == "-" {
+        let stdout = io::stdout();
+        let mut handle = stdout.lock();
+        handle.write_all(bytes)?;
+        handle.flush()
+    } else {
+        File::create(path)?.write_all(bytes)
+    }
+}
+
+// The writer for an output format. Formats without their own writer are
+// written as dot text, and rendered by graphviz. JSON graphs save [root_dir],
+// the directory that was scanned.
+fn writer_for_format<'a>(format: &str, dot_writer: DotWriter<'a>, root_dir: &Path) -> Box<dyn GraphWriter + 'a> {
+    match format {
+        "json" => Box::new(JsonWriter::new().root(root_dir)),
+        "graphml" => Box::new(GraphMlWriter::new()),
+        "gexf" => Box::new(GexfWriter::new()),
+        "mermaid" => Box::new(MermaidWriter::new()),
+        "plantuml" => Box::new(PlantUmlWriter::new()),
+        _ => Box::new(dot_writer),
+    }
+}
+
+// Write the graph in [format] to [output], rendering it with graphviz unless
+// the format is written directly. Exits if the output can't be written.
+fn write_graph_output(writer: &dyn GraphWriter, hash_graph: &HashGraph<FileNode>, format: &str, output: &str) {
+    let mut graph_text = Vec::new();
+    writer.write_graph(&mut graph_text, hash_graph).expect("Writing to memory can't fail");
+
+    // Render to the requested format with graphviz, unless it was written directly.
+    let rendered = if DIRECT_FORMATS.contains(&format) {
+        graph_text
+    } else {
+        match dot_writer::render_with_graphviz(&graph_text, format) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                eprintln!("Unable to run graphviz: {}. Is it installed?", err);
+
+                // Save the dot file, so the graph can still be rendered by hand.
+                if output != "-" {
+                    let dot_path = Path::new(output).with_extension("dot");
+                    if write_output(&dot_path.to_string_lossy(), &graph_text).is_ok() {
+                        eprintln!("Run \"dot -T{} {} > {}\" to render the graph.",
+                                  format,
+                                  dot_path.display(),
+                                  output);
+                    }
+                }
+                std::process::exit(1);
+            }
+        }
+    };
+
+    if let Err(err) = write_output(output, &rendered) {
+        eprintln!("Error writing {}: {}", output, err);
+        std::process::exit(1);
+    }
+}
+
+// -----------------------------------------------------------------------------
+
+// Load one side of a diff: a source tree to scan, or a graph saved as JSON.
+// Returns the graph, and the directory its files are under.
+fn load_diff_side(scanner: &Scanner, path: &Path) -> (HashGraph<FileNode>, PathBuf) {
+    if path.is_dir() {
+        let root_dir = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        let scan = scanner.scan_tree(&root_dir);
+        print_scan_errors(&scan.errors);
+        if !scan.unresolved.is_empty() {
+            eprintln!("Unable to locate {} includes in {}.", scan.unresolved.len(), path.display());
+        }
+        return (scan.graph, root_dir);
+    }
+
+    match json_writer::load_json_graph(path) {
+        Ok((graph, Some(root_dir))) => (graph, root_dir),
+        Ok((_, None)) => {
+            eprintln!("The graph in {} doesn't record the directory it was scanned from. \
+                       Write it again with --format json.",
+                      path.display());
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("Unable to read graph from {}: {}", path.display(), err);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Move the files under [from] to the same place under [to], so that two
+// checkouts of a project can be compared by path.
+fn rebase_graph(graph: &HashGraph<FileNode>, from: &Path, to: &Path) -> HashGraph<FileNode> {
+    let rebase = |path: &Path| match path.strip_prefix(from) {
+        Ok(relative) => to.join(relative),
+        Err(_) => path.to_path_buf(),
+    };
+    graph.merge_nodes(|node| FileNode {
+        path: if node.is_resolved() { rebase(&node.path) } else { node.path.clone() },
+        is_system: node.is_system,
+        includer_dir: node.includer_dir.as_ref().map(|dir| rebase(dir)),
+    })
+}
+
+// Compare two graphs, print the changes, and write a combined graph with the
+// added includes in green and the removed ones in red.
+fn run_diff(scanner: &Scanner, args: &ArgMatches) {
+    let (old_graph, old_root) = load_diff_side(scanner, Path::new(args.value_of("old").unwrap()));
+    let (new_graph, new_root) = load_diff_side(scanner, Path::new(args.value_of("new").unwrap()));
+    let old_graph = rebase_graph(&old_graph, &old_root, &new_root);
+
+    let diff = GraphDiff::new(&old_graph, &new_graph);
+    let graph = &diff.graph.graph;
+    let name_of = |node: &FileNode| node.path.strip_prefix(&new_root).unwrap_or(&node.path).display().to_string();
+
+    for &(title, files) in &[("Added", &diff.added_files), ("Removed", &diff.removed_files)] {
+        eprintln!("{} {} files.", title, files.len());
+        for node_idx in files.iter() {
+            eprintln!("  {}", name_of(&graph[*node_idx]));
+        }
+    }
+    for &(title, edges) in &[("Added", &diff.added_edges), ("Removed", &diff.removed_edges)] {
+        eprintln!("{} {} includes.", title, edges.len());
+        for edge_idx in edges.iter() {
+            let (src_idx, dst_idx) = graph.edge_endpoints(*edge_idx).unwrap();
+            eprintln!("  {}\n    -> {}", name_of(&graph[src_idx]), name_of(&graph[dst_idx]));
+        }
+    }
+
+    let threshold = value_t!(args, "reach-threshold", usize).unwrap_or(10);
+    let reach_changes = diff.significant_reach_changes(threshold);
+    eprintln!("Transitive include count changed by {}% or more for {} files.",
+              threshold,
+              reach_changes.len());
+    for change in reach_changes {
+        eprintln!("  {:>6} -> {:<6} {}", change.old_reach, change.new_reach, name_of(&graph[change.node]));
+    }
+
+    let mut dot_writer = DotWriter::new();
+    for edge_idx in &diff.added_edges {
+        dot_writer = dot_writer.edge_color(*edge_idx, "green");
+    }
+    for edge_idx in &diff.removed_edges {
+        dot_writer = dot_writer.edge_color(*edge_idx, "red");
+    }
+
+    let format = args.value_of("format").unwrap();
+    let output = args.value_of("output")
+        .map(String::from)
+        .unwrap_or_else(|| format!("diff.{}", format));
+    let writer = writer_for_format(format, dot_writer, &new_root);
+    write_graph_output(writer.as_ref(), &diff.graph, format, &output);
+}
 
 fn main() {
     // TODO: accept extra include paths.
@@ -90,24 +490,68 @@ fn main() {
             .help("Specify a regular expression of filenames to ignore. \nRust/RE2 \
                    syntax.\n\tExample: --exclude=\"test_|noisyFile\"")
             .takes_value(true))
-//        .arg(Arg::with_name("merge")
-//            .long("merge")
-//            .help("Granularity of the diagram: \nfile - the default, treats each file as \
-//                   separate \nmodule - merges .c/.cc/.cpp/.cxx and .h/.hpp/.hxx pairs \
-//                   \ndirectory - merges directories into one node\n")
-//            .possible_values(&MergeType::variants())
-//            .default_value("file")
-//            .takes_value(true))
-//        .arg(Arg::with_name("groups")
-//            .long("groups")
-//            .help("Cluster files or modules into directory groups")
-//            .takes_value(true))
+        .arg(Arg::with_name("exclude-glob")
+            .long("exclude-glob")
+            .help("Gitignore-style glob of paths to skip, relative to --src. Matching \
+                   directories aren't searched.\n\tExample: --exclude-glob=third_party/")
+            .multiple(true)
+            .number_of_values(1)
+            .takes_value(true))
+        .arg(Arg::with_name("include-glob")
+            .long("include-glob")
+            .help("Gitignore-style glob of the only paths to scan, relative to --src.\n\t\
+                   Example: --include-glob=src/")
+            .multiple(true)
+            .number_of_values(1)
+            .takes_value(true))
+        .arg(Arg::with_name("respect-ignore")
+            .long("respect-ignore")
+            .help("Skip the files listed in .gitignore and .ignore files, and in .git/info/exclude."))
+        .arg(Arg::with_name("no-respect-ignore")
+            .long("no-respect-ignore")
+            .conflicts_with("respect-ignore")
+            .help("Scan the files listed in ignore files, even if the config skips them."))
+        .arg(Arg::with_name("hidden")
+            .long("hidden")
+            .help("Also scan hidden directories, like .git or .cache."))
+        .arg(Arg::with_name("no-hidden")
+            .long("no-hidden")
+            .conflicts_with("hidden")
+            .help("Skip hidden directories, even if the config scans them."))
+        .arg(Arg::with_name("follow-links")
+            .long("follow-links")
+            .help("Follow symbolic links. Links that loop back to a parent directory are skipped."))
+        .arg(Arg::with_name("no-follow-links")
+            .long("no-follow-links")
+            .conflicts_with("follow-links")
+            .help("Don't follow symbolic links, even if the config does."))
+        .arg(Arg::with_name("merge")
+            .long("merge")
+            .help("Granularity of the diagram: \nfile - the default, treats each file as \
+                   separate \nmodule - merges .c/.cc/.cpp/.cxx and .h/.hpp/.hxx pairs \
+                   \ndirectory - merges directories into one node\n")
+            .possible_values(&MergeType::variants())
+            .default_value("file")
+            .takes_value(true))
+        .arg(Arg::with_name("groups")
+            .long("groups")
+            .help("Cluster files or modules into directory groups"))
+        .arg(Arg::with_name("no-groups")
+            .long("no-groups")
+            .conflicts_with("groups")
+            .help("Don't cluster files into directory groups, even if the config does."))
         .arg(Arg::with_name("include")
             .long("include")
             .help("Space separated list of include search paths. (e.g. --include= ./*/include) \
                     \tInclude a space after the equals sign for glob expansion")
             .multiple(true)
             .takes_value(true))
+        .arg(Arg::with_name("iquote")
+            .long("iquote")
+            .help("Space separated list of include search paths for quoted includes only. \
+                   Searched after the including file's directory.")
+            .multiple(true)
+            .takes_value(true))
 //        .arg(Arg::with_name("paths")
 //            .long("paths")
 //            .help("Leaves relative paths in displayed filenames.")
@@ -118,18 +562,150 @@ fn main() {
                    includes. \nangle - parse only \"system\" includes (<>) \nquote - parse only \
                    \"user\" includes (\"\")\n")
             .possible_values(&QuoteTypes::variants())
+            .case_insensitive(true)
             .default_value("quote")
             .multiple(false)
             .takes_value(true))
+        .arg(Arg::with_name("compile-commands")
+            .long("compile-commands")
+            .help("Path to a compile_commands.json file. Only the compiled files are scanned, \
+                   using their own include paths and defines.")
+            .takes_value(true))
+        .arg(Arg::with_name("compiler")
+            .long("compiler")
+            .help("GCC-compatible compiler to query for system include paths, when angle \
+                   includes are parsed. With --compile-commands, each unit's own compiler is \
+                   queried unless this is given.")
+            .default_value("cc")
+            .takes_value(true))
+        .arg(Arg::with_name("no-system-includes")
+            .long("no-system-includes")
+            .help("Don't run the compiler to detect its system include paths."))
+        .arg(Arg::with_name("define")
+            .short("D")
+            .long("define")
+            .help("Define a macro for evaluating #if blocks, as NAME or NAME=VALUE. \
+                   Includes in inactive blocks are skipped.")
+            .multiple(true)
+            .number_of_values(1)
+            .takes_value(true))
+        .arg(Arg::with_name("ext")
+            .long("ext")
+            .help("Comma separated list of file extensions to scan, e.g. --ext=h,cpp,inl. \
+                   Defaults to the common C, C++, CUDA and Objective-C extensions.")
+            .multiple(true)
+            .use_delimiter(true)
+            .number_of_values(1)
+            .takes_value(true))
+        .arg(Arg::with_name("sniff")
+            .long("sniff")
+            .help("Also scan files without an extension whose contents look like C or C++, \
+                   e.g. standard library headers."))
+        .arg(Arg::with_name("no-sniff")
+            .long("no-sniff")
+            .conflicts_with("sniff")
+            .help("Only scan files with a known extension, even if the config sniffs."))
+        .arg(Arg::with_name("conditional")
+            .long("conditional")
+            .help("Keep includes from inactive #if blocks, drawn as dashed edges."))
+        .arg(Arg::with_name("jobs")
+            .short("j")
+            .long("jobs")
+            .help("Number of threads used to scan files. Defaults to one per CPU.")
+            .takes_value(true))
+        .arg(Arg::with_name("cache")
+            .long("cache")
+            .help("File to save the includes found in the source tree to, so unchanged files \
+                   aren't read again on the next run. Several trees can share one file. \
+                   Without it, every file is scanned.")
+            .takes_value(true))
+        .arg(Arg::with_name("cost")
+            .long("cost")
+            .help("Rank headers by compile-time cost: the number of files they transitively \
+                   include, multiplied by the number of translation units that include them. \
+                   Optionally followed by the number of headers to list (default 20).")
+            .min_values(0)
+            .max_values(1)
+            .takes_value(true))
+        .arg(Arg::with_name("reduce")
+            .long("reduce")
+            .help("Remove includes that are implied by other includes (transitive reduction)."))
+        .arg(Arg::with_name("list-redundant")
+            .long("list-redundant")
+            .help("List direct includes that are implied by other includes."))
+        .arg(Arg::with_name("cycles")
+            .long("cycles")
+            .help("Report include cycles, and draw the edges that form them in red."))
+        .arg(Arg::with_name("rules")
+            .long("rules")
+            .help("File of layering rules, one per line, e.g. 'core/** may not include ui/**' \
+                   or 'ui/** may only include ui/**, core/**'. Globs are relative to --src. \
+                   Includes that break a rule are listed and drawn in orange.")
+            .takes_value(true))
+        .arg(Arg::with_name("strict")
+            .long("strict")
+            .help("Exit with an error if any includes can't be found."))
+        .arg(Arg::with_name("output")
+            .short("o")
+            .long("output")
+            .help("File to write the graph to, or '-' for stdout. Defaults to 'graph.<format>'.")
+            .takes_value(true))
+        .arg(Arg::with_name("format")
+            .long("format")
+            .help("Output format. dot, json, graphml, gexf, mermaid and plantuml are written \
+                   directly, any other format (e.g. svg, png, pdf) is rendered by graphviz.")
+            .default_value("pdf")
+            .takes_value(true))
         .arg(Arg::with_name("filter")
             .long("filter")
             .help("Specify a filename to filter by. Will only list files above or below in the tree.")
             .takes_value(true))
+        .arg(Arg::with_name("config")
+            .long("config")
+            .help("Project config file. Defaults to the nearest include2dot.toml in the source \
+                   directory or its parents. Command line options override its settings.")
+            .takes_value(true))
+        .arg(Arg::with_name("no-config")
+            .long("no-config")
+            .help("Don't read a config file."))
         .arg(Arg::with_name("src")
             .long("src")
             .help("Path to the source code, defaults to current directory.")
             .multiple(false)
             .takes_value(true))
+        .subcommand(SubCommand::with_name("check")
+            .about("Checks every include against the --rules file, and exits with an error if \
+                    any of them break a rule. No graph is written."))
+        .subcommand(SubCommand::with_name("diff")
+            .about("Compares two include graphs, e.g. before and after a change. Each can be a \
+                    source tree, which is scanned with the options given before 'diff', or a \
+                    graph saved with '--format json'.")
+            .arg(Arg::with_name("old")
+                .help("The graph before the change.")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("new")
+                .help("The graph after the change.")
+                .required(true)
+                .index(2))
+            .arg(Arg::with_name("reach-threshold")
+                .long("reach-threshold")
+                .help("Report files whose transitive include count changed by at least this \
+                       many percent.")
+                .default_value("10")
+                .takes_value(true))
+            .arg(Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .help("File to write the combined graph to, or '-' for stdout. Defaults to \
+                       'diff.<format>'.")
+                .takes_value(true))
+            .arg(Arg::with_name("format")
+                .long("format")
+                .help("Output format of the combined graph. Added includes are drawn in green, \
+                       and removed ones in red.")
+                .default_value("pdf")
+                .takes_value(true)))
         .get_matches();
 
     let root_dir = match args.value_of("src") {
@@ -138,97 +714,199 @@ fn main() {
     };
 
     if !root_dir.exists() {
-        println!("Unable to access src directory: {}", root_dir.display());
+        eprintln!("Unable to access src directory: {}", root_dir.display());
         std::process::exit(1);
     }
     // println!("Scanning directory: {}", root_dir.display());
 
-    // Collect a list of include paths to search.
-    let mut search_paths = Vec::new();
-    if let Some(values) = args.values_of("include") {
-        for string in values {
-
-            if let Ok(absolute_path) = std::fs::canonicalize(PathBuf::from(string)) {
-                println!("Using search path: {}", absolute_path.display());
-                search_paths.push(absolute_path);
-            } else {
-                println!("Unable to find absolute include path from: {}", &string);
-            }
+    let config = load_config(&args, &root_dir);
 
-        }
+    // Compare two graphs instead of drawing one.
+    if let Some(diff_args) = args.subcommand_matches("diff") {
+        run_diff(&build_scanner(&args, &config), diff_args);
+        return;
     }
 
-    // Add a list of default system include paths.
-    search_paths.push(PathBuf::from(r"C:\Program Files (x86)\Microsoft Visual Studio 14.0\VC\include"));
-
-    // Collect the type of includes to scan (<> vs "")
-    let quote_types = args.value_of("quotetypes").unwrap_or("both");
-    let (parse_user_includes, parse_system_includes) = match quote_types {
-        "angle" => (false, true),
-        "quote" => (true, false),
-        _ => (true, true), // both
+    // Load the layering rules up front, so a bad rule fails before scanning.
+    // Rules from the command line are relative to the scanned directory, and
+    // rules from the config to the config file's directory.
+    let (rules, project_dir) = match args.value_of("rules") {
+        Some(path) => {
+            let rules = LayerRules::load(Path::new(path)).unwrap_or_else(|err| {
+                eprintln!("Unable to read layering rules from {}: {}", path, err);
+                std::process::exit(1);
+            });
+            (Some(rules), std::fs::canonicalize(&root_dir).unwrap_or_else(|_| root_dir.clone()))
+        }
+        None if !config.rules.rules.is_empty() => {
+            let config_dir = config.path.parent().map(Path::to_path_buf).unwrap_or_default();
+            (Some(config.rules.clone()),
+             std::fs::canonicalize(&config_dir).unwrap_or(config_dir))
+        }
+        None => (None, PathBuf::new()),
     };
+    if args.subcommand_matches("check").is_some() && rules.is_none() {
+        eprintln!("The check command needs a --rules file, or rules in the config.");
+        std::process::exit(1);
+    }
 
-    // Restrict the file extensions to search.
-    let mut extensions = HashSet::new();
-    extensions.insert(OsString::from("c"));
-    extensions.insert(OsString::from("cc"));
-    extensions.insert(OsString::from("cpp"));
-    extensions.insert(OsString::from("cxx"));
-    extensions.insert(OsString::from("h"));
-    extensions.insert(OsString::from("hpp"));
-    extensions.insert(OsString::from("hxx"));
-
+    let scanner = build_scanner(&args, &config);
+
+    let scan = match args.value_of("compile-commands") {
+        Some(path) => {
+            let mut commands = match compile_commands::load_compile_commands(Path::new(path)) {
+                Ok(commands) => commands,
+                Err(err) => {
+                    eprintln!("Unable to read compile commands from {}: {}", path, err);
+                    std::process::exit(1);
+                }
+            };
+            if detect_system_includes(&args, &config) {
+                add_builtin_include_paths(&args, &mut commands);
+            }
+            scanner.scan_compile_commands(&commands)
+        }
+        None => scanner.scan_tree(&root_dir),
+    };
+    print_scan_errors(&scan.errors);
+    print_unresolved_includes(&scan.unresolved);
+    let unresolved_count = scan.unresolved.len();
+    let mut hash_graph = scan.graph;
+
+    // Check the includes against the rules before the graph is filtered or
+    // merged, so every violation is reported. The violating includes are
+    // remembered by their files, to highlight them in the drawn graph.
+    let mut violating_includes = Vec::new();
+    if let Some(ref rules) = rules {
+        let violations = rules.check(&hash_graph, &project_dir);
+        print_rule_violations(&hash_graph, &violations);
+        for violation in &violations {
+            let (src_idx, dst_idx) = hash_graph.graph.edge_endpoints(violation.edge).unwrap();
+            violating_includes.push((hash_graph.graph[src_idx].clone(), hash_graph.graph[dst_idx].clone()));
+        }
+    }
 
-    // Regular expression of files to exclude. Skip if exclude string is empty.
-    let exclude_regex = args.value_of("exclude")
-        .and_then(|regex_str| {
-            Regex::new(regex_str)
-                .map_err(|err| panic!("Unable to parse exclude regex: {}", err.description()))
-                .ok() // Converts successful result to Some(), discarding errors.
-        });
+    // Only check the includes against the rules, without drawing the graph.
+    if args.subcommand_matches("check").is_some() {
+        if !violating_includes.is_empty() {
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let mut hash_graph = find_includes_in_tree(&root_dir,
-                                               &search_paths,
-                                               &extensions,
-                                               parse_user_includes,
-                                               parse_system_includes,
-                                               &exclude_regex);
+    // Report the most expensive headers. Translation units are found by their
+    // extension, so this is done before files are merged into modules.
+    if args.is_present("cost") {
+        let limit = value_t!(args, "cost", usize).unwrap_or(20);
+        print_include_costs(&hash_graph, limit);
+    }
 
     // Filter the output if requested
     if let Some(filter_name) = args.value_of("filter") {
-        // Find files with the target name. For now, just use the first one.
-        let idx_list = hash_graph.find(|n| n.path.file_name().unwrap() == filter_name);
-        let root_idx = idx_list[0];
+        // Find files with the target name. Nodes are sorted by path, so if
+        // several files share the name, the first path is used.
+        let idx_list = hash_graph.find(|n| n.path.file_name() == Some(OsStr::new(filter_name)));
+        let root_idx = match idx_list.first() {
+            Some(idx) => *idx,
+            None => {
+                eprintln!("No file named {} was found.", filter_name);
+                std::process::exit(1);
+            }
+        };
+        if idx_list.len() > 1 {
+            eprintln!("Found {} files named {}, using {}",
+                      idx_list.len(),
+                      filter_name,
+                      hash_graph.graph[root_idx].path.display());
+        }
 
         hash_graph = hash_graph.filter_bidirectional(root_idx);
     }
 
-    // Write the graph to a dot file.
-    let _ = dot_writer::write_dot_with_header("./graph.dot", &hash_graph.graph);
+    // Collapse the graph to the requested granularity.
+    let merge_type = option_value(&args, "merge", &config.style.merge)
+        .unwrap_or("file")
+        .parse::<MergeType>()
+        .unwrap_or_else(|err| {
+            eprintln!("Invalid merge type in config: {}", err);
+            std::process::exit(1);
+        });
+    let merge_key: fn(&FileNode) -> FileNode = match merge_type {
+        MergeType::file => FileNode::clone,
+        MergeType::module => FileNode::to_module,
+        MergeType::directory => FileNode::to_directory,
+    };
+    hash_graph = match merge_type {
+        MergeType::file => hash_graph,
+        _ => hash_graph.merge_nodes(merge_key),
+    };
 
-    // Print summary stats
-    println!("Generated graph with {} nodes and {} edges.",
-             &hash_graph.graph.node_count(),
-             &hash_graph.graph.edge_count());
-
-    // Create a sub-process to generate a PDF of the graph
-    let graphviz_output_result = Command::new("dot")
-        .arg("-Tpdf")
-        .arg("graph.dot")
-        .output();
-
-    if let Ok(graphviz_output) = graphviz_output_result {
-        if graphviz_output.status.success() {
-            if let Err(err) = File::create("graph.pdf")
-                .map(|mut pdf_file| pdf_file.write_all(&graphviz_output.stdout)) {
-                println!("Error writing PDF: {}", err);
+    // List includes that are already implied by other includes.
+    if args.is_present("list-redundant") {
+        let redundant_edges = hash_graph.redundant_edges();
+        eprintln!("Found {} redundant direct includes.", redundant_edges.len());
+        for edge_idx in redundant_edges {
+            let (src_idx, dst_idx) = hash_graph.graph.edge_endpoints(edge_idx).unwrap();
+            eprintln!("  {}\n    -> {}",
+                      hash_graph.graph[src_idx].path.display(),
+                      hash_graph.graph[dst_idx].path.display());
+        }
+    }
+
+    // Drop the redundant edges from the output.
+    if args.is_present("reduce") {
+        hash_graph = hash_graph.transitive_reduction();
+    }
+
+    // Report include cycles, and highlight them in the graph.
+    let group_by_directory = flag_value(&args, "groups", config.style.groups);
+    let mut dot_writer = DotWriter::new().group_by_directory(group_by_directory);
+    if args.is_present("cycles") {
+        let cycles = hash_graph.find_cycles();
+        eprintln!("Found {} include cycles.", cycles.len());
+        for cycle in &cycles {
+            // Repeat the first file at the end, to close the loop.
+            let chain = cycle.iter()
+                .chain(cycle.first())
+                .map(|idx| hash_graph.graph[*idx].path.display().to_string())
+                .collect::<Vec<_>>();
+            eprintln!("  {}", chain.join("\n    -> "));
+        }
+
+        for edge_idx in hash_graph.cycle_edges() {
+            dot_writer = dot_writer.edge_color(edge_idx, config.style.cycle_color.as_deref().unwrap_or("red"));
+        }
+    }
+
+    // Highlight the includes that break the layering rules, where they are
+    // still in the graph. Merged includes are highlighted if any of the
+    // includes they stand for breaks a rule.
+    for (src_node, dst_node) in &violating_includes {
+        let endpoints = (hash_graph.node_index(&merge_key(src_node)), hash_graph.node_index(&merge_key(dst_node)));
+        if let (Some(src_idx), Some(dst_idx)) = endpoints {
+            if let Some(edge_idx) = hash_graph.graph.find_edge(src_idx, dst_idx) {
+                dot_writer = dot_writer.edge_color(edge_idx,
+                                                   config.style.violation_color.as_deref().unwrap_or("orange"));
             }
         }
-    } else {
-        println!("Unable to find graphviz. Is it installed?");
-        println!("Run \"dot -Tpdf graph.dot > graph.pdf\" to render the graph.");
     }
 
+    // Print summary stats
+    eprintln!("Generated graph with {} nodes and {} edges.",
+              &hash_graph.graph.node_count(),
+              &hash_graph.graph.edge_count());
+
+    let format = option_value(&args, "format", &config.style.format).unwrap();
+    let output = args.value_of("output")
+        .map(String::from)
+        .unwrap_or_else(|| format!("graph.{}", format));
+
+    let root_dir = std::fs::canonicalize(&root_dir).unwrap_or(root_dir);
+    let writer = writer_for_format(format, dot_writer, &root_dir);
+    write_graph_output(writer.as_ref(), &hash_graph, format, &output);
 
+    if args.is_present("strict") && unresolved_count > 0 {
+        eprintln!("Found {} unresolved includes in strict mode.", unresolved_count);
+        std::process::exit(1);
+    }
 }
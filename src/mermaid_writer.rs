@@ -0,0 +1,88 @@
+
+use std::io::{self, Write};
+
+use petgraph::visit::EdgeRef;
+
+use file_node::FileNode;
+use hash_graph::HashGraph;
+use writer::{node_identifiers, GraphWriter};
+
+// Writes graphs as a Mermaid flowchart, for embedding in Markdown. Inactive
+// includes are drawn as dotted arrows.
+#[derive(Debug, Clone, Default)]
+pub struct MermaidWriter;
+
+impl MermaidWriter {
+    pub fn new() -> MermaidWriter {
+        MermaidWriter
+    }
+}
+
+impl GraphWriter for MermaidWriter {
+    fn write_graph(&self, out: &mut dyn Write, graph: &HashGraph<FileNode>) -> Result<(), io::Error> {
+        let ids = node_identifiers(&graph.graph);
+
+        writeln!(out, "graph LR")?;
+
+        // Nodes
+        // Format:
+        //     main_cpp["main.cpp"]
+        for node_idx in graph.graph.node_indices() {
+            let label = graph.graph[node_idx].path.file_name().unwrap_or_default().to_string_lossy();
+            writeln!(out, "    {}[\"{}\"]", ids[node_idx.index()], escape_label(&label))?;
+        }
+
+        // Edges
+        // Format:
+        //     main_cpp --> a_h
+        //     main_cpp -.-> b_h
+        for edge in graph.graph.edge_references() {
+            let arrow = if edge.weight().is_active { "-->" } else { "-.->" };
+            writeln!(out,
+                     "    {} {} {}",
+                     ids[edge.source().index()],
+                     arrow,
+                     ids[edge.target().index()])?;
+        }
+
+        Ok(())
+    }
+}
+
+// Escape a quoted Mermaid label, using its entity codes.
+fn escape_label(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '#' => escaped.push_str("#35;"),
+            '"' => escaped.push_str("#quot;"),
+            '<' => escaped.push_str("#lt;"),
+            '>' => escaped.push_str("#gt;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_write_mermaid() {
+    use hash_graph::IncludeEdge;
+
+    let mut graph = HashGraph::new();
+    graph.add_edge(FileNode::new("/src/main.cpp", false),
+                   FileNode::new("/src/odd\"#name.h", false),
+                   IncludeEdge {
+                       is_active: false,
+                       line: 1,
+                       spelling: String::from("\"odd\"#name.h\""),
+                   });
+
+    let mut out = Vec::new();
+    MermaidWriter::new().write_graph(&mut out, &graph).unwrap();
+
+    assert_eq!(String::from_utf8(out).unwrap(),
+               "graph LR\n    main_cpp[\"main.cpp\"]\n    odd__name_h[\"odd#quot;#35;name.h\"]\n    \
+                main_cpp -.-> odd__name_h\n");
+}
@@ -0,0 +1,111 @@
+
+use std::path::Path;
+
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+// ----------------------------------------------------------------------------
+
+// Gitignore-style globs choosing which files of a tree are scanned, e.g.
+//
+//     third_party/
+//     build*/
+//     *_test.cpp
+//     !/src/core/*_test.cpp
+//
+// Paths are matched relative to the root of the scan. As in .gitignore files,
+// a glob without a '/' matches at any depth, a leading '/' anchors it to the
+// root, a trailing '/' only matches directories, and a leading '!' makes an
+// exception to the globs before it.
+//
+// A file is scanned if it isn't excluded and, when there are include globs,
+// matches one of them. Excluded directories aren't walked at all, so as in
+// git, nothing under an excluded directory can be brought back with '!'.
+#[derive(Debug, Clone)]
+pub struct PathFilter {
+    include: Option<Gitignore>, // None scans every file that isn't excluded.
+    exclude: Gitignore,
+}
+
+impl Default for PathFilter {
+    fn default() -> PathFilter {
+        PathFilter {
+            include: None,
+            exclude: Gitignore::empty(),
+        }
+    }
+}
+
+impl PathFilter {
+    pub fn new<I, J, S, T>(include: I, exclude: J) -> Result<PathFilter, String>
+        where I: IntoIterator<Item = S>,
+              J: IntoIterator<Item = T>,
+              S: AsRef<str>,
+              T: AsRef<str>
+    {
+        let include = build_matcher(include)?;
+        Ok(PathFilter {
+            include: if include.is_empty() { None } else { Some(include) },
+            exclude: build_matcher(exclude)?,
+        })
+    }
+
+    // True if the directory at [dir], relative to the root, is skipped along
+    // with everything under it.
+    pub fn excludes_dir(&self, dir: &Path) -> bool {
+        self.exclude.matched(dir, true).is_ignore()
+    }
+
+    // True if the file at [file], relative to the root, should be scanned.
+    pub fn includes_file(&self, file: &Path) -> bool {
+        if self.exclude.matched_path_or_any_parents(file, false).is_ignore() {
+            return false;
+        }
+        match self.include {
+            Some(ref include) => include.matched_path_or_any_parents(file, false).is_ignore(),
+            None => true,
+        }
+    }
+}
+
+// Compile a list of globs into a single matcher, rooted at the scanned tree.
+fn build_matcher<I, S>(globs: I) -> Result<Gitignore, String>
+    where I: IntoIterator<Item = S>,
+          S: AsRef<str>
+{
+    let mut builder = GitignoreBuilder::new("");
+    for glob in globs {
+        builder.add_line(None, glob.as_ref()).map_err(|err| err.to_string())?;
+    }
+    builder.build().map_err(|err| err.to_string())
+}
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_path_filter() {
+    let filter = PathFilter::new(Vec::<&str>::new(),
+                                 vec!["third_party/", "/build*/", "*_test.cpp", "!/src/core/*_test.cpp"])
+        .unwrap();
+
+    // Directories are pruned by the globs ending in '/', at any depth unless
+    // anchored.
+    assert!(filter.excludes_dir(Path::new("third_party")));
+    assert!(filter.excludes_dir(Path::new("lib/third_party")));
+    assert!(filter.excludes_dir(Path::new("build-debug")));
+    assert!(!filter.excludes_dir(Path::new("src/build")));
+
+    assert!(filter.includes_file(Path::new("src/main.cpp")));
+    assert!(!filter.includes_file(Path::new("src/main_test.cpp")));
+    assert!(filter.includes_file(Path::new("src/core/main_test.cpp")));
+    assert!(!filter.includes_file(Path::new("lib/third_party/zlib/zlib.h")));
+    assert!(filter.includes_file(Path::new("src/build/config.h")));
+
+    // With include globs, only matching files are scanned.
+    let filter = PathFilter::new(vec!["src/", "*.h"], vec!["src/gen/"]).unwrap();
+    assert!(filter.includes_file(Path::new("src/core/a.cpp")));
+    assert!(filter.includes_file(Path::new("include/a.h")));
+    assert!(!filter.includes_file(Path::new("tools/a.cpp")));
+    assert!(!filter.includes_file(Path::new("src/gen/a.h")));
+
+    assert!(PathFilter::new(vec!["src/[z-a]"], Vec::<&str>::new()).is_err());
+}
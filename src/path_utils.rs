@@ -1,21 +1,18 @@
 
-extern crate walkdir;
-use walkdir::DirEntry;
+use ignore::DirEntry;
 
 use regex::Regex;
 
 #[allow(unused_imports)]
 use std::env;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 // ----------------------------------------------------------------------------
 
-#[allow(dead_code)]
+// True for hidden entries below the root of a walk, like '.git'. The root
+// itself is never hidden, even when it's '.' or './'.
 pub fn is_hidden(entry: &DirEntry) -> bool {
-    entry.file_name()
-        .to_str()
-        .map(|s| s.starts_with('.'))
-        .unwrap_or(false)
+    entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.')
 }
 
 // ----------------------------------------------------------------------------
@@ -38,32 +35,131 @@ pub fn normalize_path_separators(path: &Path) -> PathBuf {
 
 // ----------------------------------------------------------------------------
 
-// Convert a relative include path (e.g. <Windows.h>) into an absolute path.
-pub fn convert_to_absolute_path(relative_path: &Path,
-                                local_search_path: &Path,
-                                system_search_paths: &[PathBuf])
-                                -> Option<PathBuf> {
+// Remove '.' and '..' components without touching the file system, so the
+// same file reached through different spellings gets the same path. A '..'
+// that can't be resolved, e.g. at the start of a relative path, is kept.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                match normalized.components().next_back() {
+                    Some(Component::Normal(_)) => {
+                        normalized.pop();
+                    }
+                    Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                    _ => normalized.push(component),
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
 
-    // Search relative to the local directory first.
-    let full_path = local_search_path.join(&relative_path);
-    if full_path.exists() {
-        return Some(full_path);
+// ----------------------------------------------------------------------------
+
+// Include search paths, split by which kinds of include search them.
+#[derive(Debug, Clone, Default)]
+pub struct SearchPaths {
+    pub quote: Vec<PathBuf>, // Only searched by "" includes (-iquote).
+    pub angle: Vec<PathBuf>, // Searched by both "" and <> includes (-I, -isystem, system paths).
+}
+
+impl SearchPaths {
+    // Append another set of search paths, keeping quote and angle paths apart.
+    pub fn extend(&mut self, other: &SearchPaths) {
+        self.quote.extend(other.quote.iter().cloned());
+        self.angle.extend(other.angle.iter().cloned());
     }
-    //    else {
-    //        println!("Unable to locate {:?}", full_path);
-    //    }
-
-    // Then search system include paths
-    for search_prefix in system_search_paths {
-        let full_path = search_prefix.join(&relative_path);
-        if full_path.exists() {
-            return Some(full_path);
+}
+
+// The way an include directive asks for a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncludeKind {
+    Quote, // #include "file"
+    Angle, // #include <file>
+    Next, // #include_next, with either quote type
+}
+
+// Return the directories searched for an include, following the compiler's
+// lookup order:
+// * Quote includes search the including file's directory, then the quote
+//   paths, then the angle paths.
+// * Angle includes search only the angle paths.
+// * #include_next searches the quote and angle paths, starting after the
+//   directory the including file was found in.
+pub fn search_directories(including_file: &Path,
+                          kind: IncludeKind,
+                          search_paths: &SearchPaths)
+                          -> Vec<PathBuf> {
+
+    let all_paths = search_paths.quote.iter().chain(&search_paths.angle);
+
+    match kind {
+        IncludeKind::Quote => {
+            including_file.parent()
+                .map(Path::to_path_buf)
+                .into_iter()
+                .chain(all_paths.cloned())
+                .collect()
         }
-        //        else {
-        //            println!("Unable to locate {:?}", full_path);
-        //        }
+        IncludeKind::Angle => search_paths.angle.clone(),
+        IncludeKind::Next => {
+            let all_paths = all_paths.cloned().collect::<Vec<_>>();
+
+            // The including file was found in the deepest search path that
+            // contains it. If it wasn't found through a search path (e.g. a
+            // source file), search everything, like a normal #include.
+            let found_idx = all_paths.iter()
+                .enumerate()
+                .filter(|&(_, dir)| including_file.starts_with(dir))
+                .max_by_key(|&(_, dir)| dir.components().count())
+                .map(|(idx, _)| idx);
+
+            match found_idx {
+                Some(idx) => all_paths[idx + 1..].to_vec(),
+                None => all_paths,
+            }
+        }
+    }
+}
+
+// Convert a relative include path (e.g. <Windows.h>) into an absolute path,
+// by checking each of the search_directories() in turn.
+pub fn convert_to_absolute_path(relative_path: &Path,
+                                including_file: &Path,
+                                kind: IncludeKind,
+                                search_paths: &SearchPaths)
+                                -> Option<PathBuf> {
+    search_directories(including_file, kind, search_paths)
+        .iter()
+        .map(|dir| normalize_path(&dir.join(relative_path)))
+        .find(|full_path| full_path.is_file())
+}
+
+// ----------------------------------------------------------------------------
+
+// Return the deepest directory that contains all the given directories, or
+// an empty path if there are none.
+pub fn common_ancestor<'a, I>(dirs: I) -> PathBuf
+    where I: IntoIterator<Item = &'a Path>
+{
+    let mut common_dir: Option<PathBuf> = None;
+    for dir in dirs {
+        common_dir = Some(match common_dir {
+            None => dir.to_path_buf(),
+            Some(prev) => {
+                prev.components()
+                    .zip(dir.components())
+                    .take_while(|&(a, b)| a == b)
+                    .map(|(a, _)| a.as_os_str())
+                    .collect()
+            }
+        });
     }
-    None
+    common_dir.unwrap_or_default()
 }
 
 // ----------------------------------------------------------------------------
@@ -76,7 +172,10 @@ pub fn convert_to_absolute_path(relative_path: &Path,
 pub fn filename_matches_regex(regex: &Option<Regex>, path: &Path) -> bool {
 
     let filename = path.file_name().map_or("", |name| name.to_str().unwrap_or(""));
-    regex.as_ref().map_or(false, |rx| rx.is_match(filename))
+    match *regex {
+        Some(ref rx) => rx.is_match(filename),
+        None => false,
+    }
     // let is_match = regex.as_ref().map_or(false, |ref rx| rx.is_match(filename));
     // println!("checking {:?}: {}", path, is_match);
     // is_match
@@ -92,7 +191,10 @@ pub fn filename_matches_regex(regex: &Option<Regex>, path: &Path) -> bool {
 pub fn name_matches_regex(regex: &Option<Regex>, name: &Path) -> bool {
 
     let filename = name.to_str().unwrap_or("");
-    regex.as_ref().map_or(false, |rx| rx.is_match(filename))
+    match *regex {
+        Some(ref rx) => rx.is_match(filename),
+        None => false,
+    }
     // let is_match = regex.as_ref().map_or(false, |ref rx| rx.is_match(filename));
     // println!("checking {:?}: {}", name, is_match);
     // is_match
@@ -117,3 +219,47 @@ fn test_relative_path() {
     println!("{:?}", file_b);
     assert!(file_b.exists());
 }
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_include_lookup_order() {
+    let example_dir = env::current_dir().unwrap().join("testdata").join("lookup");
+    let including_file = example_dir.join("src").join("main.cpp");
+
+    let search_paths = SearchPaths {
+        quote: vec![example_dir.join("quote")],
+        angle: vec![example_dir.join("first"), example_dir.join("second")],
+    };
+
+    let lookup = |name: &str, from: &Path, kind: IncludeKind| {
+        convert_to_absolute_path(Path::new(name), from, kind, &search_paths)
+            .map(|path| path.strip_prefix(&example_dir).unwrap().to_path_buf())
+    };
+
+    // Quote includes prefer the local directory, then the quote paths.
+    assert_eq!(lookup("local.h", &including_file, IncludeKind::Quote),
+               Some(Path::new("src").join("local.h")));
+    assert_eq!(lookup("quoted.h", &including_file, IncludeKind::Quote),
+               Some(Path::new("quote").join("quoted.h")));
+    assert_eq!(lookup("config.h", &including_file, IncludeKind::Quote),
+               Some(Path::new("first").join("config.h")));
+
+    // Angle includes skip the local directory and the quote paths.
+    assert_eq!(lookup("local.h", &including_file, IncludeKind::Angle), None);
+    assert_eq!(lookup("quoted.h", &including_file, IncludeKind::Angle), None);
+    assert_eq!(lookup("config.h", &including_file, IncludeKind::Angle),
+               Some(Path::new("first").join("config.h")));
+
+    // #include_next continues after the directory of the including file.
+    let first_config = example_dir.join("first").join("config.h");
+    assert_eq!(lookup("config.h", &first_config, IncludeKind::Next),
+               Some(Path::new("second").join("config.h")));
+    assert_eq!(lookup("config.h", &including_file, IncludeKind::Next),
+               Some(Path::new("first").join("config.h")));
+    // Relative spellings resolve to the same normalized path.
+    assert_eq!(lookup("../src/./local.h", &first_config, IncludeKind::Quote),
+               Some(Path::new("src").join("local.h")));
+    assert_eq!(normalize_path(Path::new("../a/./b/../c.h")), Path::new("../a/c.h"));
+    assert_eq!(normalize_path(Path::new("/../a.h")), Path::new("/a.h"));
+}
@@ -0,0 +1,78 @@
+
+use std::io::{self, Write};
+
+use petgraph::visit::EdgeRef;
+
+use file_node::FileNode;
+use hash_graph::HashGraph;
+use writer::{node_identifiers, GraphWriter};
+
+// Writes graphs as a PlantUML component diagram. Inactive includes are drawn
+// as dotted arrows.
+#[derive(Debug, Clone, Default)]
+pub struct PlantUmlWriter;
+
+impl PlantUmlWriter {
+    pub fn new() -> PlantUmlWriter {
+        PlantUmlWriter
+    }
+}
+
+impl GraphWriter for PlantUmlWriter {
+    fn write_graph(&self, out: &mut dyn Write, graph: &HashGraph<FileNode>) -> Result<(), io::Error> {
+        let ids = node_identifiers(&graph.graph);
+
+        writeln!(out, "@startuml")?;
+
+        // Nodes
+        // Format:
+        //     component "main.cpp" as main_cpp
+        for node_idx in graph.graph.node_indices() {
+            let label = graph.graph[node_idx].path.file_name().unwrap_or_default().to_string_lossy();
+            writeln!(out, "component \"{}\" as {}", escape_label(&label), ids[node_idx.index()])?;
+        }
+
+        // Edges
+        // Format:
+        //     main_cpp --> a_h
+        //     main_cpp ..> b_h
+        for edge in graph.graph.edge_references() {
+            let arrow = if edge.weight().is_active { "-->" } else { "..>" };
+            writeln!(out,
+                     "{} {} {}",
+                     ids[edge.source().index()],
+                     arrow,
+                     ids[edge.target().index()])?;
+        }
+
+        writeln!(out, "@enduml")
+    }
+}
+
+// PlantUML strings can't contain a quote, so write it as a unicode escape.
+fn escape_label(text: &str) -> String {
+    text.replace('"', "<U+0022>")
+}
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_write_plantuml() {
+    use hash_graph::IncludeEdge;
+
+    let mut graph = HashGraph::new();
+    graph.add_edge(FileNode::new("/src/main.cpp", false),
+                   FileNode::new("vector", true),
+                   IncludeEdge {
+                       is_active: true,
+                       line: 1,
+                       spelling: String::from("<vector>"),
+                   });
+
+    let mut out = Vec::new();
+    PlantUmlWriter::new().write_graph(&mut out, &graph).unwrap();
+
+    assert_eq!(String::from_utf8(out).unwrap(),
+               "@startuml\ncomponent \"main.cpp\" as main_cpp\ncomponent \"vector\" as vector\n\
+                main_cpp --> vector\n@enduml\n");
+}
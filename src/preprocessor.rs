@@ -0,0 +1,488 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+// ----------------------------------------------------------------------------
+
+// Macro definitions, mapping a macro name to its replacement text.
+// Function-like macros are stored with an empty replacement.
+pub type MacroMap = HashMap<String, String>;
+
+// Parse a command line definition of the form NAME or NAME=VALUE.
+// Like the compiler's -D flag, a bare NAME is defined as 1.
+pub fn parse_define(definition: &str) -> (String, String) {
+    match definition.find('=') {
+        Some(pos) => (definition[..pos].trim().to_string(), definition[pos + 1..].trim().to_string()),
+        None => (definition.trim().to_string(), String::from("1")),
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+// State of one #if/#elif/#else/#endif block.
+#[derive(Debug)]
+struct Conditional {
+    parent_active: bool, // Whether the enclosing block is live.
+    is_active: bool, // Whether the current branch is live.
+    branch_taken: bool, // Whether any branch so far has been live.
+}
+
+// A minimal C preprocessor that tracks conditional blocks and macro
+// definitions, so includes inside dead branches can be told apart.
+// Each file is processed on its own: macros defined in included headers are
+// not seen by the including file.
+#[derive(Debug)]
+pub struct Preprocessor {
+    macros: MacroMap,
+    conditionals: Vec<Conditional>,
+}
+
+impl Preprocessor {
+    pub fn new(predefined: &MacroMap) -> Preprocessor {
+        Preprocessor {
+            macros: predefined.clone(),
+            conditionals: Vec::new(),
+        }
+    }
+
+    // True if lines at the current position are compiled.
+    pub fn is_active(&self) -> bool {
+        match self.conditionals.last() {
+            Some(cond) => cond.is_active,
+            None => true,
+        }
+    }
+
+    // Update the preprocessor state for a directive, e.g. ("ifdef", "FOO").
+    // Directives that don't affect conditional state are ignored.
+    pub fn process_directive(&mut self, directive: &str, args: &str) {
+        let args = strip_comments(args);
+        let args = args.trim();
+
+        match directive {
+            "if" | "ifdef" | "ifndef" => {
+                let parent_active = self.is_active();
+                let condition = parent_active &&
+                                match directive {
+                    "ifdef" => self.macros.contains_key(first_word(args)),
+                    "ifndef" => !self.macros.contains_key(first_word(args)),
+                    _ => self.evaluate(args) != 0,
+                };
+                self.conditionals.push(Conditional {
+                    parent_active,
+                    is_active: condition,
+                    branch_taken: condition,
+                });
+            }
+            "elif" => {
+                let condition = match self.conditionals.last() {
+                    Some(cond) => cond.parent_active && !cond.branch_taken && self.evaluate(args) != 0,
+                    None => return,
+                };
+                if let Some(cond) = self.conditionals.last_mut() {
+                    cond.is_active = condition;
+                    cond.branch_taken |= condition;
+                }
+            }
+            "else" => {
+                if let Some(cond) = self.conditionals.last_mut() {
+                    cond.is_active = cond.parent_active && !cond.branch_taken;
+                    cond.branch_taken = true;
+                }
+            }
+            "endif" => {
+                self.conditionals.pop();
+            }
+            "define" if self.is_active() => {
+                let name_len = args.find(|c: char| !is_identifier_char(c)).unwrap_or(args.len());
+                let (name, rest) = args.split_at(name_len);
+                if name.is_empty() {
+                    return;
+                }
+                // Function-like macros have a '(' directly after the name.
+                let value = if rest.starts_with('(') { "" } else { rest.trim() };
+                self.macros.insert(name.to_string(), value.to_string());
+            }
+            "undef" if self.is_active() => {
+                self.macros.remove(first_word(args));
+            }
+            _ => {}
+        }
+    }
+
+    // Evaluate a #if expression. Unknown identifiers are 0, as in C.
+    // Malformed expressions also evaluate to 0.
+    pub fn evaluate(&self, expr: &str) -> i64 {
+        self.evaluate_with_depth(expr, 0)
+    }
+
+    fn evaluate_with_depth(&self, expr: &str, depth: usize) -> i64 {
+        let tokens = tokenize(expr);
+        let mut parser = ExprParser {
+            preprocessor: self,
+            tokens: &tokens,
+            pos: 0,
+            depth,
+        };
+        parser.parse_ternary().unwrap_or(0)
+    }
+
+    // Value of an identifier used in an expression, expanding object-like
+    // macros. Recursion is capped so self-referencing macros terminate.
+    fn identifier_value(&self, name: &str, depth: usize) -> i64 {
+        match name {
+            "true" => 1,
+            "false" => 0,
+            _ => {
+                match self.macros.get(name) {
+                    Some(value) if depth < MAX_EXPANSION_DEPTH => {
+                        self.evaluate_with_depth(value, depth + 1)
+                    }
+                    _ => 0,
+                }
+            }
+        }
+    }
+}
+
+const MAX_EXPANSION_DEPTH: usize = 16;
+
+// ----------------------------------------------------------------------------
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn first_word(text: &str) -> &str {
+    text.split(|c: char| !is_identifier_char(c)).next().unwrap_or("")
+}
+
+// Remove '//' and '/* */' comments from a single directive line.
+fn strip_comments(text: &str) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    loop {
+        let line_comment = rest.find("//");
+        let block_comment = rest.find("/*");
+        match (line_comment, block_comment) {
+            (Some(line_pos), Some(block_pos)) if line_pos < block_pos => {
+                result.push_str(&rest[..line_pos]);
+                return result;
+            }
+            (Some(line_pos), None) => {
+                result.push_str(&rest[..line_pos]);
+                return result;
+            }
+            (_, Some(block_pos)) => {
+                result.push_str(&rest[..block_pos]);
+                result.push(' ');
+                match rest[block_pos + 2..].find("*/") {
+                    Some(end_pos) => rest = &rest[block_pos + 2 + end_pos + 2..],
+                    None => return result,
+                }
+            }
+            (None, None) => {
+                result.push_str(rest);
+                return result;
+            }
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Identifier(String),
+    Operator(&'static str),
+}
+
+// Operators, longest first so that e.g. '<<' is not read as two '<'.
+const OPERATORS: [&str; 24] = ["<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "(", ")", "!",
+                               "~", "*", "/", "%", "+", "-", "<", ">", "&", "^", "|", "?", ":"];
+
+fn tokenize(expr: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            tokens.push(Token::Number(read_number(&mut chars)));
+        } else if is_identifier_char(c) {
+            let mut name = String::new();
+            while let Some(&c) = chars.peek() {
+                if !is_identifier_char(c) {
+                    break;
+                }
+                name.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Identifier(name));
+        } else if c == '\'' {
+            chars.next();
+            let value = chars.next().map_or(0, |c| c as i64);
+            for c in chars.by_ref() {
+                if c == '\'' {
+                    break;
+                }
+            }
+            tokens.push(Token::Number(value));
+        } else {
+            let rest = chars.clone().take(2).collect::<String>();
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => {
+                    for _ in 0..op.len() {
+                        chars.next();
+                    }
+                    tokens.push(Token::Operator(op));
+                }
+                None => {
+                    // Unknown character. Skip it rather than failing the whole expression.
+                    chars.next();
+                }
+            }
+        }
+    }
+
+    tokens
+}
+
+// Read a decimal, octal or hex integer literal, ignoring any u/l suffix.
+fn read_number(chars: &mut Peekable<Chars>) -> i64 {
+    let mut digits = String::new();
+    while let Some(&c) = chars.peek() {
+        if !c.is_alphanumeric() {
+            break;
+        }
+        digits.push(c);
+        chars.next();
+    }
+
+    let lowercase = digits.to_lowercase();
+    let lowercase = lowercase.trim_end_matches(['u', 'l']);
+    let result = if let Some(hex) = lowercase.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if lowercase.len() > 1 && lowercase.starts_with('0') {
+        i64::from_str_radix(&lowercase[1..], 8)
+    } else {
+        lowercase.parse::<i64>()
+    };
+    result.unwrap_or(0)
+}
+
+// ----------------------------------------------------------------------------
+
+// Recursive descent parser for #if expressions, following C operator precedence.
+// Returns None for malformed input.
+struct ExprParser<'a> {
+    preprocessor: &'a Preprocessor,
+    tokens: &'a [Token],
+    pos: usize,
+    depth: usize,
+}
+
+// Binary operators from lowest to highest precedence.
+const BINARY_PRECEDENCE: [&[&str]; 10] = [&["||"],
+                                          &["&&"],
+                                          &["|"],
+                                          &["^"],
+                                          &["&"],
+                                          &["==", "!="],
+                                          &["<", "<=", ">", ">="],
+                                          &["<<", ">>"],
+                                          &["+", "-"],
+                                          &["*", "/", "%"]];
+
+impl<'a> ExprParser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn accept(&mut self, op: &str) -> bool {
+        match self.peek() {
+            Some(&Token::Operator(next_op)) if next_op == op => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn parse_ternary(&mut self) -> Option<i64> {
+        let condition = self.parse_binary(0)?;
+        if self.accept("?") {
+            let if_true = self.parse_ternary()?;
+            if !self.accept(":") {
+                return None;
+            }
+            let if_false = self.parse_ternary()?;
+            Some(if condition != 0 { if_true } else { if_false })
+        } else {
+            Some(condition)
+        }
+    }
+
+    fn parse_binary(&mut self, level: usize) -> Option<i64> {
+        if level == BINARY_PRECEDENCE.len() {
+            return self.parse_unary();
+        }
+
+        let mut lhs = self.parse_binary(level + 1)?;
+        loop {
+            let op = match self.peek() {
+                Some(&Token::Operator(op)) if BINARY_PRECEDENCE[level].contains(&op) => op,
+                _ => return Some(lhs),
+            };
+            self.pos += 1;
+            let rhs = self.parse_binary(level + 1)?;
+            lhs = match op {
+                "||" => ((lhs != 0) || (rhs != 0)) as i64,
+                "&&" => ((lhs != 0) && (rhs != 0)) as i64,
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "==" => (lhs == rhs) as i64,
+                "!=" => (lhs != rhs) as i64,
+                "<" => (lhs < rhs) as i64,
+                "<=" => (lhs <= rhs) as i64,
+                ">" => (lhs > rhs) as i64,
+                ">=" => (lhs >= rhs) as i64,
+                "<<" => lhs.wrapping_shl(rhs as u32),
+                ">>" => lhs.wrapping_shr(rhs as u32),
+                "+" => lhs.wrapping_add(rhs),
+                "-" => lhs.wrapping_sub(rhs),
+                "*" => lhs.wrapping_mul(rhs),
+                "/" => lhs.checked_div(rhs)?,
+                "%" => lhs.checked_rem(rhs)?,
+                _ => return None,
+            };
+        }
+    }
+
+    fn parse_unary(&mut self) -> Option<i64> {
+        match self.next()? {
+            Token::Operator("!") => self.parse_unary().map(|v| (v == 0) as i64),
+            Token::Operator("~") => self.parse_unary().map(|v| !v),
+            Token::Operator("-") => self.parse_unary().map(|v| v.wrapping_neg()),
+            Token::Operator("+") => self.parse_unary(),
+            Token::Operator("(") => {
+                let value = self.parse_ternary()?;
+                if self.accept(")") { Some(value) } else { None }
+            }
+            Token::Number(value) => Some(value),
+            Token::Identifier(ref name) if name == "defined" => {
+                let has_parens = self.accept("(");
+                let result = match self.next()? {
+                    Token::Identifier(name) => self.preprocessor.macros.contains_key(&name) as i64,
+                    _ => return None,
+                };
+                if has_parens && !self.accept(")") {
+                    return None;
+                }
+                Some(result)
+            }
+            Token::Identifier(name) => {
+                // Function-like macro calls (e.g. __has_include(...)) can't be
+                // evaluated here, so skip the arguments and treat them as 0.
+                if self.accept("(") {
+                    let mut nesting = 1;
+                    while nesting > 0 {
+                        match self.next()? {
+                            Token::Operator("(") => nesting += 1,
+                            Token::Operator(")") => nesting -= 1,
+                            _ => {}
+                        }
+                    }
+                    Some(0)
+                } else {
+                    Some(self.preprocessor.identifier_value(&name, self.depth))
+                }
+            }
+            Token::Operator(_) => None,
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn evaluate(expr: &str) -> i64 {
+        let mut macros = MacroMap::new();
+        macros.insert(String::from("ONE"), String::from("1"));
+        macros.insert(String::from("VERSION"), String::from("(ONE + 2)"));
+        macros.insert(String::from("EMPTY"), String::new());
+        Preprocessor::new(&macros).evaluate(expr)
+    }
+
+    #[test]
+    fn evaluate_expressions() {
+        assert_eq!(evaluate("0"), 0);
+        assert_eq!(evaluate("1 + 2 * 3"), 7);
+        assert_eq!(evaluate("(1 + 2) * 3"), 9);
+        assert_eq!(evaluate("0x10 | 010"), 24);
+        assert_eq!(evaluate("1 << 4 == 16"), 1);
+        assert_eq!(evaluate("!0 && ~0"), 1);
+        assert_eq!(evaluate("-1 < 0 ? 5 : 6"), 5);
+        assert_eq!(evaluate("1 / 0"), 0);
+        assert_eq!(evaluate("VERSION >= 3"), 1);
+        assert_eq!(evaluate("UNKNOWN"), 0);
+        assert_eq!(evaluate("defined(EMPTY) && defined ONE"), 1);
+        assert_eq!(evaluate("defined(UNKNOWN)"), 0);
+        assert_eq!(evaluate("__has_include(<foo.h>) || 2"), 1);
+        assert_eq!(evaluate("1 +"), 0);
+    }
+
+    #[test]
+    fn conditional_blocks() {
+        let mut pp = Preprocessor::new(&MacroMap::new());
+
+        pp.process_directive("ifdef", "FOO");
+        assert!(!pp.is_active());
+        pp.process_directive("elif", "1 // comment");
+        assert!(pp.is_active());
+        pp.process_directive("else", "");
+        assert!(!pp.is_active());
+        pp.process_directive("endif", "");
+        assert!(pp.is_active());
+
+        // Definitions inside inactive blocks are ignored.
+        pp.process_directive("if", "0");
+        pp.process_directive("define", "FOO");
+        pp.process_directive("endif", "");
+        pp.process_directive("ifndef", "FOO");
+        assert!(pp.is_active());
+
+        // Nested blocks stay inactive when the parent is inactive.
+        pp.process_directive("define", "FOO 1");
+        pp.process_directive("if", "!FOO");
+        pp.process_directive("if", "1");
+        assert!(!pp.is_active());
+        pp.process_directive("else", "");
+        assert!(!pp.is_active());
+        pp.process_directive("endif", "");
+        pp.process_directive("endif", "");
+        pp.process_directive("undef", "FOO");
+        pp.process_directive("if", "FOO");
+        assert!(!pp.is_active());
+    }
+
+    #[test]
+    fn parse_definitions() {
+        assert_eq!(parse_define("FOO"), (String::from("FOO"), String::from("1")));
+        assert_eq!(parse_define("FOO=2"), (String::from("FOO"), String::from("2")));
+        assert_eq!(parse_define("FOO="), (String::from("FOO"), String::new()));
+    }
+}
@@ -0,0 +1,251 @@
+
+use std::io::{self, Read, Write};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use std::time::UNIX_EPOCH;
+
+use serde_json::{self, Value};
+
+use file_node::FileNode;
+use find_includes::Include;
+use io_utils::invalid_data;
+use preprocessor::MacroMap;
+
+// Bump this when the saved format, or the way files are scanned, changes.
+const CACHE_VERSION: u64 = 1;
+
+// ----------------------------------------------------------------------------
+
+// The size and modification time of a file, used to tell if it changed since
+// it was last scanned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStamp {
+    pub size: u64,
+    pub modified_secs: u64,
+    pub modified_nanos: u32,
+}
+
+impl FileStamp {
+    // Stamp a file on disk. None if its metadata can't be read.
+    pub fn of(path: &Path) -> Option<FileStamp> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+
+        Some(FileStamp {
+            size: metadata.len(),
+            modified_secs: modified.as_secs(),
+            modified_nanos: modified.subsec_nanos(),
+        })
+    }
+}
+
+// Includes found by previous scans, saved between runs so that unchanged
+// files don't need to be read again. Files are scanned with a set of macros,
+// so a cache is only valid for the macros it was made with.
+#[derive(Debug, Clone, Default)]
+pub struct ScanCache {
+    defines: String,
+    entries: HashMap<PathBuf, (FileStamp, Vec<Include>)>,
+}
+
+impl ScanCache {
+    // An empty cache for files scanned with [defines].
+    pub fn new(defines: &MacroMap) -> ScanCache {
+        ScanCache {
+            defines: defines_key(defines),
+            entries: HashMap::new(),
+        }
+    }
+
+    // Read a cache file. If it was made by another version, or with other
+    // macros, an empty cache is returned instead.
+    pub fn load(path: &Path, defines: &MacroMap) -> Result<ScanCache, io::Error> {
+        let mut f = File::open(path)?;
+        let mut text = String::new();
+        f.read_to_string(&mut text)?;
+
+        let cache = parse_cache(&text)?;
+        if cache.defines == defines_key(defines) {
+            Ok(cache)
+        } else {
+            Ok(ScanCache::new(defines))
+        }
+    }
+
+    // Write the cache to a file.
+    pub fn save(&self, path: &Path) -> Result<(), io::Error> {
+        let mut f = File::create(path)?;
+        serde_json::to_writer(&mut f, &self.to_json())?;
+        writeln!(f)
+    }
+
+    // The includes of [file], if it hasn't changed since it was cached.
+    pub fn get(&self, file: &Path, stamp: FileStamp) -> Option<&[Include]> {
+        match self.entries.get(file) {
+            Some((cached_stamp, includes)) if *cached_stamp == stamp => Some(includes),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&mut self, file: PathBuf, stamp: FileStamp, includes: Vec<Include>) {
+        self.entries.insert(file, (stamp, includes));
+    }
+
+    // Drop the entries of files that no longer exist.
+    pub fn remove_missing_files(&mut self) {
+        self.entries.retain(|file, _| file.is_file());
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // Convert the cache to JSON, sorted by path. Files whose paths aren't
+    // valid UTF-8 are left out, and will be scanned again.
+    fn to_json(&self) -> Value {
+        let mut paths = self.entries.keys().filter(|path| path.to_str().is_some()).collect::<Vec<_>>();
+        paths.sort();
+
+        let files = paths.into_iter()
+            .map(|path| {
+                let (stamp, ref includes) = self.entries[path];
+                let includes = includes.iter()
+                    .filter(|inc| inc.file.path.to_str().is_some())
+                    .map(|inc| {
+                        json!({
+                            "name": inc.file.path.to_str(),
+                            "system": inc.file.is_system,
+                            "active": inc.is_active,
+                            "next": inc.is_next,
+                            "line": inc.line,
+                            "column": inc.column,
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                json!({
+                    "path": path.to_str(),
+                    "size": stamp.size,
+                    "modified": [stamp.modified_secs, stamp.modified_nanos],
+                    "includes": includes,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        json!({
+            "version": CACHE_VERSION,
+            "defines": self.defines,
+            "files": files,
+        })
+    }
+}
+
+// Macros sorted into a single string, for comparing the macros of two scans.
+fn defines_key(defines: &MacroMap) -> String {
+    let mut defines_list = defines.iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>();
+    defines_list.sort();
+    defines_list.join("\n")
+}
+
+// Parse the text of a cache file, in the format written by ScanCache::save().
+fn parse_cache(text: &str) -> Result<ScanCache, io::Error> {
+    let json: Value = serde_json::from_str(text).map_err(|err| invalid_data(&err.to_string()))?;
+
+    if json.get("version").and_then(Value::as_u64) != Some(CACHE_VERSION) {
+        return Ok(ScanCache::default());
+    }
+
+    let defines = json.get("defines")
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid_data("Scan cache is missing \"defines\""))?;
+    let files = json.get("files")
+        .and_then(Value::as_array)
+        .ok_or_else(|| invalid_data("Scan cache is missing \"files\""))?;
+
+    let mut cache = ScanCache {
+        defines: String::from(defines),
+        entries: HashMap::new(),
+    };
+
+    for file in files {
+        let path = file.get("path")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid_data("Cached file is missing \"path\""))?;
+        let size = file.get("size").and_then(Value::as_u64);
+        let modified = file.get("modified").and_then(Value::as_array);
+        let stamp = match (size, modified.map(Vec::as_slice)) {
+            (Some(size), Some([secs, nanos])) => {
+                FileStamp {
+                    size,
+                    modified_secs: secs.as_u64().unwrap_or(0),
+                    modified_nanos: nanos.as_u64().unwrap_or(0) as u32,
+                }
+            }
+            _ => return Err(invalid_data("Cached file is missing \"size\" or \"modified\"")),
+        };
+
+        let mut includes = Vec::new();
+        for include in file.get("includes").and_then(Value::as_array).into_iter().flatten() {
+            let name = include.get("name")
+                .and_then(Value::as_str)
+                .ok_or_else(|| invalid_data("Cached include is missing \"name\""))?;
+            let flag = |key| include.get(key).and_then(Value::as_bool).unwrap_or(false);
+            let number = |key| include.get(key).and_then(Value::as_u64).unwrap_or(0) as usize;
+
+            includes.push(Include {
+                file: FileNode::new(name, flag("system")),
+                is_active: flag("active"),
+                is_next: flag("next"),
+                line: number("line"),
+                column: number("column"),
+            });
+        }
+
+        cache.insert(PathBuf::from(path), stamp, includes);
+    }
+
+    Ok(cache)
+}
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_cache_round_trip() {
+    let mut defines = MacroMap::new();
+    defines.insert(String::from("DEBUG"), String::from("1"));
+
+    let stamp = FileStamp {
+        size: 120,
+        modified_secs: 1500000000,
+        modified_nanos: 42,
+    };
+    let include = Include {
+        file: FileNode::new("vector", true),
+        is_active: false,
+        is_next: true,
+        line: 3,
+        column: 1,
+    };
+
+    let mut cache = ScanCache::new(&defines);
+    cache.insert(PathBuf::from("/src/main.cpp"), stamp, vec![include]);
+
+    let parsed = parse_cache(&cache.to_json().to_string()).unwrap();
+    assert_eq!(parsed.defines, "DEBUG=1");
+    assert_eq!(parsed.len(), 1);
+
+    let includes = parsed.get(Path::new("/src/main.cpp"), stamp).unwrap();
+    assert_eq!(includes[0].spelling(), "<vector>");
+    assert_eq!((includes[0].is_active, includes[0].is_next), (false, true));
+    assert_eq!((includes[0].line, includes[0].column), (3, 1));
+
+    // A changed file is scanned again.
+    let touched = FileStamp { modified_secs: 1500000001, ..stamp };
+    assert!(parsed.get(Path::new("/src/main.cpp"), touched).is_none());
+}
@@ -0,0 +1,72 @@
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+// ----------------------------------------------------------------------------
+
+// Ask a GCC-compatible compiler for its system include search paths.
+// Runs the equivalent of 'cc -E -v -x c++ - < /dev/null' and reads the
+// search list it prints to stderr.
+pub fn detect_system_include_paths(compiler: &str) -> Result<Vec<PathBuf>, io::Error> {
+    let output = Command::new(compiler)
+        .args(["-E", "-v", "-x", "c++", "-"])
+        .stdin(Stdio::null())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(io::Error::other(format!("{} exited with {}", compiler, output.status)));
+    }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Ok(parse_search_list(&stderr))
+}
+
+// Fall back to the INCLUDE environment variable, which is how the MSVC
+// developer prompt passes system include paths to cl.exe.
+pub fn include_paths_from_env() -> Vec<PathBuf> {
+    env::var_os("INCLUDE")
+        .map(|paths| env::split_paths(&paths).filter(|p| !p.as_os_str().is_empty()).collect())
+        .unwrap_or_default()
+}
+
+// Parse the verbose compiler output, which looks like:
+//
+//     #include "..." search starts here:
+//     #include <...> search starts here:
+//      /usr/include/c++/9
+//      /usr/include
+//      /System/Library/Frameworks (framework directory)
+//     End of search list.
+//
+// Framework directories are skipped, since they don't map headers to paths directly.
+fn parse_search_list(output: &str) -> Vec<PathBuf> {
+    output.lines()
+        .skip_while(|line| !line.starts_with("#include <...> search starts here:"))
+        .skip(1)
+        .take_while(|line| !line.starts_with("End of search list."))
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.ends_with("(framework directory)"))
+        .map(PathBuf::from)
+        .collect()
+}
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_parse_search_list() {
+    let output = r#"Using built-in specs.
+ignoring nonexistent directory "/usr/local/include/x86_64-linux-gnu"
+#include "..." search starts here:
+ /opt/quote
+#include <...> search starts here:
+ /usr/include/c++/9
+ /usr/include
+ /System/Library/Frameworks (framework directory)
+End of search list.
+ /not/a/path
+"#;
+
+    assert_eq!(parse_search_list(output),
+               vec![PathBuf::from("/usr/include/c++/9"), PathBuf::from("/usr/include")]);
+}
@@ -0,0 +1,157 @@
+use std::io::{self, Write};
+use std::ffi::OsStr;
+use std::path::Path;
+use std::collections::HashSet;
+
+use petgraph::Graph;
+use petgraph::prelude::NodeIndex;
+
+use file_node::{FileKind, FileNode};
+use hash_graph::{HashGraph, IncludeEdge};
+use path_utils;
+
+// ----------------------------------------------------------------------------
+
+/// An output format for include graphs. Implement this to add a new format.
+pub trait GraphWriter {
+    /// Write the whole graph to `out`.
+    fn write_graph(&self, out: &mut dyn Write, graph: &HashGraph<FileNode>) -> Result<(), io::Error>;
+}
+
+// ----------------------------------------------------------------------------
+
+/// Attributes of a node that tools like Gephi and yEd can colour and size by.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeAttributes {
+    pub label: String, // File name, without the directory.
+    pub path: String,
+    pub directory: String, // Empty for unresolved includes.
+    pub extension: String, // Without the dot. Empty if there is none.
+    pub kind: FileKind,
+    pub is_system: bool,
+    pub fan_in: usize, // Number of files that directly include this one.
+    pub fan_out: usize, // Number of files this one directly includes.
+}
+
+impl NodeAttributes {
+    pub fn new(graph: &HashGraph<FileNode>, node_idx: NodeIndex) -> NodeAttributes {
+        let node = &graph.graph[node_idx];
+        let to_string = |name: Option<&OsStr>| name.map(|s| s.to_string_lossy().into_owned());
+
+        NodeAttributes {
+            label: to_string(node.path.file_name()).unwrap_or_default(),
+            path: node.path.to_string_lossy().into_owned(),
+            directory: to_string(node.path.parent().map(Path::as_os_str)).unwrap_or_default(),
+            extension: to_string(node.path.extension()).unwrap_or_default(),
+            kind: node.kind(),
+            is_system: node.is_system,
+            fan_in: graph.includers_of(node).len(),
+            fan_out: graph.includes_of(node).len(),
+        }
+    }
+}
+
+/// Escape text for use in XML content or attribute values.
+pub fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Words that can't be used as node identifiers in dot, Mermaid or PlantUML.
+const RESERVED_IDENTIFIERS: [&str; 13] = ["end", "graph", "subgraph", "flowchart", "style", "class",
+                                          "classdef", "click", "component", "node", "edge", "digraph",
+                                          "strict"];
+
+/// Return an identifier for every node, derived from its path, that can be used
+/// unquoted in text formats like dot, Mermaid and PlantUML. The directory shared by
+/// all files is left out, then only ASCII letters, digits and '_' are kept. A
+/// numeric suffix keeps the identifiers unique. E.g. with files in '/project',
+/// '/project/src/foo-bar.h' becomes 'src_foo_bar_h'.
+pub fn node_identifiers(graph: &Graph<FileNode, IncludeEdge>) -> Vec<String> {
+    let root_dir = path_utils::common_ancestor(graph.node_indices()
+        .map(|node_idx| &graph[node_idx])
+        .filter(|node| node.path.is_absolute())
+        .filter_map(|node| node.path.parent()));
+    let mut used = HashSet::new();
+
+    graph.node_indices()
+        .map(|node_idx| {
+            let node_path = &graph[node_idx].path;
+            let path = node_path.strip_prefix(&root_dir).unwrap_or(node_path).to_string_lossy();
+            let mut base = path.chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect::<String>()
+                .trim_matches('_')
+                .to_string();
+
+            if !base.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                base.insert_str(0, "n_");
+            }
+            if RESERVED_IDENTIFIERS.contains(&base.to_lowercase().as_str()) {
+                base.push('_');
+            }
+
+            let mut id = base.clone();
+            let mut suffix = 2;
+            while !used.insert(id.clone()) {
+                id = format!("{}_{}", base, suffix);
+                suffix += 1;
+            }
+            id
+        })
+        .collect()
+}
+
+// ----------------------------------------------------------------------------
+
+#[test]
+fn test_node_attributes() {
+    let mut graph = HashGraph::new();
+    let edge = IncludeEdge {
+        is_active: true,
+        line: 1,
+        spelling: String::from("\"a.h\""),
+    };
+    graph.add_edge(FileNode::new("/src/main.cpp", false), FileNode::new("/src/a.h", false), edge.clone());
+    graph.add_edge(FileNode::new("/src/b.cpp", false), FileNode::new("/src/a.h", false), edge);
+
+    let a_idx = graph.node_index(&FileNode::new("/src/a.h", false)).unwrap();
+    assert_eq!(NodeAttributes::new(&graph, a_idx),
+               NodeAttributes {
+                   label: String::from("a.h"),
+                   path: String::from("/src/a.h"),
+                   directory: String::from("/src"),
+                   extension: String::from("h"),
+                   kind: FileKind::Header,
+                   is_system: false,
+                   fan_in: 2,
+                   fan_out: 0,
+               });
+
+    assert_eq!(escape_xml("<a & \"b\">"), "&lt;a &amp; &quot;b&quot;&gt;");
+}
+
+#[test]
+fn test_node_identifiers() {
+    let mut graph = HashGraph::new();
+    let edge = IncludeEdge {
+        is_active: true,
+        line: 1,
+        spelling: String::new(),
+    };
+    graph.add_edge(FileNode::new("/src/foo-bar.h", false), FileNode::new("/src/foo_bar.h", false), edge.clone());
+    graph.add_edge(FileNode::new("/src/foo-bar.h", false), FileNode::new("3d/vec.h", false), edge.clone());
+    graph.add_edge(FileNode::new("/src/foo-bar.h", false), FileNode::new("end", true), edge);
+
+    assert_eq!(node_identifiers(&graph.graph), vec!["foo_bar_h", "foo_bar_h_2", "n_3d_vec_h", "end_"]);
+}
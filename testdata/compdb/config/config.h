@@ -0,0 +1 @@
+// Found through -iquote.
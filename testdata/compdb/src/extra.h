@@ -0,0 +1 @@
+// Only included when USE_EXTRA is defined.
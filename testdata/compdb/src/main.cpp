@@ -0,0 +1,6 @@
+#include "config.h"
+#include <lib/api.h>
+
+#if USE_EXTRA
+#include "extra.h"
+#endif
@@ -0,0 +1,2 @@
+// Not in the compilation database.
+#include "unused.h"
@@ -0,0 +1,10 @@
+#ifndef ALWAYS_H
+#define ALWAYS_H
+
+#define USE_EXTRA 1
+#if USE_EXTRA
+#include "extra.h"
+#endif
+#undef USE_EXTRA
+
+#endif // ALWAYS_H
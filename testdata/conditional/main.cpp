@@ -0,0 +1,14 @@
+#include "always.h"
+
+#if 0
+#include "disabled.h"
+#endif
+
+#ifdef _WIN32
+#include "windows_only.h"
+#elif defined(__linux__) && \
+      VERSION >= 2
+#include "linux_only.h"
+#else
+#include "fallback.h"
+#endif
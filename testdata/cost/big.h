@@ -0,0 +1,2 @@
+#include "base.h"
+#include "loop.h"
@@ -0,0 +1 @@
+#include "base.h"
@@ -0,0 +1 @@
+#include "big.h"
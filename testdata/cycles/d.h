@@ -0,0 +1 @@
+#include "e.h"
@@ -0,0 +1,3 @@
+#include "a.h"
+#include "c.h"
+#include "self.h"
@@ -0,0 +1,2 @@
+// Wraps the next config.h in the search path.
+#include_next <config.h>
@@ -0,0 +1 @@
+// Found through the quote search paths.
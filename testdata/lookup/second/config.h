@@ -0,0 +1 @@
+// The real config.h.
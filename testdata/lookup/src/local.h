@@ -0,0 +1 @@
+// Only visible to quote includes from src/.
@@ -0,0 +1,3 @@
+#include "local.h"
+#include "quoted.h"
+#include <config.h>
@@ -0,0 +1,2 @@
+#include "bar.h"
+#include "util/strings.h"
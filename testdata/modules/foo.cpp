@@ -0,0 +1,2 @@
+#include "foo.h"
+#include "util/strings.h"
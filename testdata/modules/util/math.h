@@ -0,0 +1 @@
+// Leaf header.
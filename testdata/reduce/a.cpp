@@ -0,0 +1,4 @@
+#include "b.h"
+#include "c.h"
+#include "d.h"
+#include "b.h"
@@ -0,0 +1,2 @@
+#include "d.h"
+#include "e.h"
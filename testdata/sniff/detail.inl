@@ -0,0 +1 @@
+inline int answer() { return 42; }
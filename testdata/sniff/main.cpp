@@ -0,0 +1,5 @@
+#include "config"
+
+int main() {
+    return 0;
+}